@@ -15,6 +15,7 @@ cp ma-central/Server/data_auth.db data_auth.db                          # copy n
 cp ma-central/Server/.example.env .env                                  # # #
 cp ma-central/Server/update.sh update.sh                                # copy update script
 cp -r ma-central/Server/passes passes                                   #
+cp -r ma-central/Server/filter filter                                   # username filter term lists
 chmod +x update.sh                                                      # make it executatble
 cp ma-central/Server/service.sh service.sh                              # copy service management script
 chmod +x service.sh                                                     # make it executatble
@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{db_auth, db_main, timezone};
+
+#[derive(Serialize)]
+pub struct AttendanceRow {
+    pub ticket_id: i64,
+    pub user_id: i64,
+    pub student_id: String,
+    pub full_name: String,
+    pub username: String,
+    pub status: String,
+    pub occurrence: i64,
+    pub reserved_at: i64,
+    pub checked_in_at: Option<i64>,
+}
+
+#[derive(Serialize, Default)]
+pub struct AttendanceTotals {
+    pub tickets: usize,
+    pub checked_in: usize,
+    pub reserved: usize,
+    pub no_shows: usize, // reservations never checked in after the event ended
+    pub void: usize,
+}
+
+#[derive(Serialize)]
+pub struct AttendanceReport {
+    pub event_id: i64,
+    pub title: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub timezone: String, // the zone times in the csv are written in
+    pub capacity: Option<i64>,
+    pub totals: AttendanceTotals,
+    pub attendees: Vec<AttendanceRow>,
+}
+
+pub fn report(event: &db_main::Event, tickets: Vec<db_main::Ticket>, users: &HashMap<i64, db_auth::User>, now: i64) -> AttendanceReport {
+    let mut totals = AttendanceTotals { tickets: tickets.len(), ..Default::default() };
+    let duration = event.end_time - event.start_time;
+    let mut attendees = Vec::new();
+    for ticket in tickets {
+        match ticket.status.as_str() {
+            "checked_in" => totals.checked_in += 1,
+            "void" => totals.void += 1,
+            _ => {
+                totals.reserved += 1;
+                let start = if ticket.occurrence == 0 { event.start_time } else { ticket.occurrence };
+                if start + duration < now {
+                    totals.no_shows += 1;
+                }
+            }
+        }
+        let holder = users.get(&ticket.holder_id);
+        attendees.push(AttendanceRow {
+            ticket_id: ticket.id,
+            user_id: ticket.holder_id,
+            student_id: holder.map(|holder| holder.student_id.clone()).unwrap_or_default(),
+            full_name: holder.map(|holder| holder.full_name.clone()).unwrap_or_default(),
+            username: holder.map(|holder| holder.username.clone()).unwrap_or_default(),
+            status: ticket.status,
+            occurrence: ticket.occurrence,
+            reserved_at: ticket.creation_date,
+            checked_in_at: (ticket.checkin_date != 0).then_some(ticket.checkin_date),
+        });
+    }
+    // sorted the way teachers read rosters
+    attendees.sort_by(|a, b| a.full_name.to_lowercase().cmp(&b.full_name.to_lowercase()).then(a.occurrence.cmp(&b.occurrence)));
+    AttendanceReport {
+        event_id: event.id,
+        title: event.title.clone(),
+        start_time: event.start_time,
+        end_time: event.end_time,
+        timezone: event.timezone.clone(),
+        capacity: event.capacity,
+        totals,
+        attendees,
+    }
+}
+
+// one row per ticket with local times, then the totals
+pub fn to_csv(report: &AttendanceReport) -> String {
+    let zone = timezone::zone(&report.timezone);
+    let local_time = |millis: i64| timezone::to_local(&zone, millis).map(|time| time.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+    let mut lines = vec!["student_id,full_name,username,status,occurrence,reserved_at,checked_in_at".to_string()];
+    for row in &report.attendees {
+        lines.push(
+            [
+                field(&row.student_id),
+                field(&row.full_name),
+                field(&row.username),
+                field(&row.status),
+                if row.occurrence == 0 { "".to_string() } else { local_time(row.occurrence) },
+                local_time(row.reserved_at),
+                row.checked_in_at.map(local_time).unwrap_or_default(),
+            ]
+            .join(","),
+        );
+    }
+    lines.push("".to_string());
+    lines.push(format!("tickets,{}", report.totals.tickets));
+    lines.push(format!("checked_in,{}", report.totals.checked_in));
+    lines.push(format!("reserved,{}", report.totals.reserved));
+    lines.push(format!("no_shows,{}", report.totals.no_shows));
+    lines.push(format!("void,{}", report.totals.void));
+    lines.join("\r\n") + "\r\n"
+}
+
+fn field(value: &str) -> String {
+    // quoted when needed, and leading formula characters neutralized so spreadsheets don't evaluate names
+    let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
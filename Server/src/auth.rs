@@ -7,9 +7,10 @@ use argon2::{
 };
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::sync::RwLock;
+use serde_json::json;
+use std::{env, sync::RwLock};
 
-use crate::db_auth;
+use crate::{db_auth, filter, impersonate};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LoginForm {
@@ -23,19 +24,67 @@ pub struct CreateForm {
     full_name: String,
     username: String,
     password: String,
+    #[serde(default)]
+    invite_code: String,
+}
+
+// SIGNUP_MODE=gated requires a valid invite code. emails aren't verified, so they can't stand in for one
+fn signup_gated() -> bool {
+    env::var("SIGNUP_MODE").map(|mode| mode == "gated").unwrap_or(false)
+}
+
+// usernames, passwords and names are limited to printable ascii
+pub fn field_allowed(value: &str) -> bool {
+    let regex = Regex::new(r"^[a-z0-9A-Z- ~!@#$%^&*()=+/\_[_]{}|?.,]{3,64}$").unwrap();
+    regex.is_match(value)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProfileForm {
+    username: String,
+    full_name: String,
+}
+
+// both names can end up public. Err holds the blocked term, Ok holds (field, value, matched term) for each name that needs admin review
+fn screen_names(username: &str, full_name: &str) -> Result<Vec<(String, String, String)>, String> {
+    let mut reviews = Vec::new();
+    for (field, value, verdict) in [("username", username, filter::check_name(username)), ("full_name", full_name, filter::check_full_name(full_name))] {
+        match verdict {
+            filter::Verdict::Blocked(term) => return Err(term),
+            filter::Verdict::Review(term) => reviews.push((field.to_string(), value.to_string(), term)),
+            filter::Verdict::Allowed => {}
+        }
+    }
+    Ok(reviews)
+}
+
+async fn queue_name_reviews(pool: &db_auth::Pool, user_id: i64, reviews: Vec<(String, String, String)>) {
+    for (field, value, matched) in reviews {
+        if db_auth::create_name_review(pool, user_id, field, value, matched).await.is_err() {
+            log::warn!("could not queue name review for user {}", user_id);
+        }
+    }
 }
 
 pub async fn create_account(pool: &db_auth::Pool, create_form: web::Json<CreateForm>) -> impl Responder {
     // check password length is between 8 and 32, inclusive
     if create_form.password.len() >= 8 && create_form.password.len() <= 64 {
         // check if user is a sketchy motherfucker
-        let regex = Regex::new(r"^[a-z0-9A-Z- ~!@#$%^&*()=+/\_[_]{}|?.,]{3,64}$").unwrap();
-        if !regex.is_match(&create_form.username) || !regex.is_match(&create_form.password) || !regex.is_match(&create_form.full_name) {
+        if !field_allowed(&create_form.username) || !field_allowed(&create_form.password) || !field_allowed(&create_form.full_name) {
             return HttpResponse::BadRequest()
                 .status(StatusCode::from_u16(400).unwrap())
                 .insert_header(("Cache-Control", "no-cache"))
                 .body("{\"status\": \"you_sketchy_motherfucker\"}");
         }
+        // check names against the content filter
+        let name_reviews = match screen_names(&create_form.username, &create_form.full_name) {
+            Ok(reviews) => reviews,
+            Err(_) => {
+                return HttpResponse::BadRequest()
+                    .insert_header(("Cache-Control", "no-cache"))
+                    .body("{\"status\": \"name_blocked\"}");
+            }
+        };
         // check if username is taken
         let target_user_temp: Result<db_auth::User, actix_web::Error> = db_auth::get_user_username(pool, create_form.username.clone()).await;
         if target_user_temp.is_ok() {
@@ -52,27 +101,39 @@ pub async fn create_account(pool: &db_auth::Pool, create_form: web::Json<CreateF
                     .insert_header(("Cache-Control", "no-cache"))
                     .body("{\"status\": \"student_id_taken\"}");
             } else {
-                // insert into database
-                let user_temp: Result<db_auth::User, actix_web::Error> = db_auth::create_user(
+                if signup_gated() && create_form.invite_code.is_empty() {
+                    return HttpResponse::Forbidden()
+                        .insert_header(("Cache-Control", "no-cache"))
+                        .body("{\"status\": \"invite_required\"}");
+                }
+                // insert into database. invite codes tag the account with a cohort, even when signup is open
+                let user_temp: Result<db_auth::Signup, actix_web::Error> = db_auth::create_user(
                     pool,
                     create_form.student_id.clone(),
                     create_form.full_name.clone(),
                     create_form.username.clone(),
                     create_form.password.clone(),
+                    create_form.invite_code.clone(),
                 )
                 .await;
                 // send final success/failure for creation
-                if user_temp.is_err() {
-                    return HttpResponse::BadRequest()
-                        .status(StatusCode::from_u16(500).unwrap())
-                        .insert_header(("Cache-Control", "no-cache"))
-                        .body("{\"status\": \"creation_error\"}");
-                } else {
-                    drop(user_temp);
-                    return HttpResponse::Ok()
-                        .status(StatusCode::from_u16(200).unwrap())
+                match user_temp {
+                    Ok(db_auth::Signup::InviteInvalid) => HttpResponse::Forbidden()
                         .insert_header(("Cache-Control", "no-cache"))
-                        .body("{\"status\": \"success\"}");
+                        .body("{\"status\": \"invite_invalid\"}"),
+                    Err(_) => {
+                        return HttpResponse::BadRequest()
+                            .status(StatusCode::from_u16(500).unwrap())
+                            .insert_header(("Cache-Control", "no-cache"))
+                            .body("{\"status\": \"creation_error\"}");
+                    }
+                    Ok(db_auth::Signup::Created(new_user)) => {
+                        queue_name_reviews(pool, new_user.id, name_reviews).await;
+                        return HttpResponse::Ok()
+                            .status(StatusCode::from_u16(200).unwrap())
+                            .insert_header(("Cache-Control", "no-cache"))
+                            .body("{\"status\": \"success\"}");
+                    }
                 }
             }
         }
@@ -101,7 +162,7 @@ pub async fn login(
             .body("{\"status\": \"bad_s1\"}");
     }
     // query was OK, unwrap and set to target_user
-    let target_user = target_user_temp.unwrap();
+    let mut target_user = target_user_temp.unwrap();
 
     // ensure the target user id exists
     if target_user.id != 0 {
@@ -115,17 +176,32 @@ pub async fn login(
                 .insert_header(("Cache-Control", "no-cache"))
                 .body("{\"status\": \"bad_s2\"}");
         }
+        let parsed_hash = parsed_hash.unwrap();
+        let hash_outdated = db_auth::hash_outdated(&parsed_hash);
         // check that the provided password's hash is equal to the correct password's hash
         if Argon2::default()
-            .verify_password(login_form.password.as_bytes(), &parsed_hash.unwrap())
+            .verify_password(login_form.password.as_bytes(), &parsed_hash)
             .is_ok()
         {
+            // only reveal a suspension once the password checks out
+            if target_user.suspended {
+                return HttpResponse::Forbidden()
+                    .insert_header(("Cache-Control", "no-cache"))
+                    .json(json!({ "status": "suspended", "reason": target_user.suspension_reason }));
+            }
             if admin_restriction && target_user.data != "admin" {
                 return HttpResponse::Forbidden()
                     .status(StatusCode::from_u16(400).unwrap())
                     .insert_header(("Cache-Control", "no-cache"))
                     .body("{\"status\": \"bad_s5\"}");
             } else {
+                // the password is known right now, so bring old hashes up to the current argon2 policy
+                if hash_outdated {
+                    match db_auth::update_password(pool, target_user.id, login_form.password.clone()).await {
+                        Ok(new_hash) => target_user.pass_hash = new_hash,
+                        Err(_) => log::warn!("could not rehash password for user {}", target_user.id),
+                    }
+                }
                 // save the username to the identity
                 identity.remember(login_form.username.clone());
                 // write the user object to the session
@@ -200,6 +276,8 @@ pub async fn logout(session: web::Data<RwLock<crate::Sessions>>, identity: Ident
     if let Some(id) = identity.identity() {
         // forget identity
         identity.forget();
+        // logging out of a "view as student" session ends it
+        impersonate::remove(&session, &id);
         // remove user object from the user hashmap
         session.write().unwrap().user_map.remove(&id);
     }
@@ -209,3 +287,47 @@ pub async fn logout(session: web::Data<RwLock<crate::Sessions>>, identity: Ident
         .insert_header(("Cache-Control", "no-cache"))
         .body("done")
 }
+
+pub async fn update_profile(
+    pool: &db_auth::Pool,
+    session: web::Data<RwLock<crate::Sessions>>,
+    identity: Identity,
+    user: db_auth::User,
+    profile_form: web::Json<ProfileForm>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if !field_allowed(&profile_form.username) || !field_allowed(&profile_form.full_name) {
+        return Ok(HttpResponse::BadRequest()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"you_sketchy_motherfucker\"}"));
+    }
+    let name_reviews = match screen_names(&profile_form.username, &profile_form.full_name) {
+        Ok(reviews) => reviews,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest()
+                .insert_header(("Cache-Control", "no-cache"))
+                .body("{\"status\": \"name_blocked\"}"));
+        }
+    };
+    if profile_form.username != user.username && db_auth::get_user_username(pool, profile_form.username.clone()).await.is_ok() {
+        return Ok(HttpResponse::Conflict()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"username_taken\"}"));
+    }
+    db_auth::update_profile(pool, user.id, profile_form.username.clone(), profile_form.full_name.clone()).await?;
+    queue_name_reviews(pool, user.id, name_reviews).await;
+    // sessions are keyed by username, so move this one over to the new name
+    let updated_user = db_auth::get_user_username(pool, profile_form.username.clone()).await?;
+    if let Some(id) = identity.identity() {
+        session.write().unwrap().user_map.remove(&id);
+    }
+    identity.remember(updated_user.username.clone());
+    session.write().unwrap().user_map.insert(updated_user.username.clone(), updated_user);
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .body("{\"status\": \"success\"}"))
+}
+
+// drop every cached session for an account so moderation changes take effect immediately
+pub fn end_user_sessions(session: &web::Data<RwLock<crate::Sessions>>, user_id: i64) {
+    session.write().unwrap().user_map.retain(|_, user| user.id != user_id);
+}
@@ -0,0 +1,182 @@
+use once_cell::sync::Lazy;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde::Deserialize;
+use std::{collections::HashMap, env, sync::Mutex};
+
+use crate::{db_main::Event, geo};
+
+// how students may check themselves in. off leaves check-in to admins scanning tickets, code means the rotating code shown at the event
+pub const MODES: [&str; 4] = ["off", "location", "code", "location_code"];
+
+// codes change every 30 seconds, and the previous one is still taken so students typing as it rolls over aren't turned away
+pub const CODE_PERIOD_MS: i64 = 30_000;
+const CODE_DIGITS: u32 = 6;
+
+// wrong codes allowed per student and event in a window, so six digits can't be guessed by brute force
+const MAX_CODE_ATTEMPTS: u32 = 5;
+const ATTEMPT_WINDOW_MS: i64 = 5 * 60_000;
+// (event id, user id) -> (wrong codes, window start)
+type Attempts = HashMap<(i64, i64), (u32, i64)>;
+static CODE_ATTEMPTS: Lazy<Mutex<Attempts>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn default_mode() -> String {
+    "off".to_string()
+}
+
+fn env_minutes(key: &str, default: i64) -> i64 {
+    env::var(key).ok().and_then(|value| value.parse::<i64>().ok()).unwrap_or(default)
+}
+
+// offsets are whole minutes, up to a week
+const MAX_WINDOW_MINUTES: i64 = 10_080;
+
+pub fn window_valid(opens_before: Option<i64>, closes_after: Option<i64>) -> bool {
+    [opens_before, closes_after].iter().all(|minutes| minutes.is_none_or(|minutes| (0..=MAX_WINDOW_MINUTES).contains(&minutes)))
+}
+
+pub fn window_error() -> serde_json::Value {
+    serde_json::json!({ "status": "bad_window", "max": MAX_WINDOW_MINUTES })
+}
+
+fn default_opens_before() -> i64 {
+    env_minutes("CHECKIN_OPENS_MINUTES", 30)
+}
+
+fn default_closes_after() -> i64 {
+    env_minutes("CHECKIN_CLOSES_MINUTES", 30)
+}
+
+// officers can narrow the window but not stretch it past the server defaults, or they could check in anyone at any time
+pub fn officer_window_allowed(opens_before: Option<i64>, closes_after: Option<i64>) -> bool {
+    opens_before.is_none_or(|minutes| minutes <= default_opens_before()) && closes_after.is_none_or(|minutes| minutes <= default_closes_after())
+}
+
+pub fn officer_window_error() -> serde_json::Value {
+    serde_json::json!({ "status": "window_too_wide", "max_opens_before": default_opens_before(), "max_closes_after": default_closes_after() })
+}
+
+// when check-in opens and closes, in epoch millis. events without their own offsets use the server defaults
+pub fn window(event: &Event) -> (i64, i64) {
+    let opens_before = event.checkin_opens_before.unwrap_or_else(default_opens_before);
+    let closes_after = event.checkin_closes_after.unwrap_or_else(default_closes_after);
+    // saturating, for offsets stored before they were checked
+    (event.start_time.saturating_sub(opens_before.saturating_mul(60_000)), event.end_time.saturating_add(closes_after.saturating_mul(60_000)))
+}
+
+// None while check-in is open, otherwise the status explaining why not
+pub fn window_status(event: &Event, now: i64) -> Option<&'static str> {
+    let (opens, closes) = window(event);
+    if now < opens {
+        Some("checkin_not_open")
+    } else if now > closes {
+        Some("checkin_closed")
+    } else {
+        None
+    }
+}
+
+fn default_radius() -> f64 {
+    env::var("SELF_CHECKIN_RADIUS_M").ok().and_then(|value| value.parse::<f64>().ok()).unwrap_or(150.0)
+}
+
+#[derive(Deserialize)]
+pub struct SelfCheckinData {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub accuracy: Option<f64>, // meters, as reported by the device
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CheckinWindowSettings {
+    pub opens_before: Option<i64>, // minutes before start_time, none for the server default
+    pub closes_after: Option<i64>, // minutes after end_time, none for the server default
+}
+
+#[derive(Deserialize)]
+pub struct SelfCheckinSettings {
+    pub mode: String,
+    pub radius: Option<i64>, // meters, the server default when missing
+}
+
+// people who can manage an event can read its code and set its window, so only admins may check themselves in to events they run
+pub fn self_checkin_allowed(is_admin: bool, manages_event: bool) -> bool {
+    is_admin || !manages_event
+}
+
+// whether a student may check themselves in right now from where they say they are, with the code they were shown. errors are status strings
+pub fn verify(event: &Event, user_id: i64, data: &SelfCheckinData, now: i64) -> Result<(), &'static str> {
+    if event.self_checkin == "off" {
+        return Err("self_checkin_disabled");
+    }
+    if let Some(status) = window_status(event, now) {
+        return Err(status);
+    }
+    if event.self_checkin == "location" || event.self_checkin == "location_code" {
+        let (latitude, longitude) = data.latitude.zip(data.longitude).ok_or("location_required")?;
+        let radius = event.checkin_radius.map(|radius| radius as f64).unwrap_or_else(default_radius);
+        // a fix vaguer than the fence itself can't place anyone inside it
+        if data.accuracy.is_some_and(|accuracy| accuracy > radius) {
+            return Err("location_inaccurate");
+        }
+        if geo::distance_m(latitude, longitude, event.latitude, event.longitude) > radius {
+            return Err("too_far");
+        }
+    }
+    if event.self_checkin == "code" || event.self_checkin == "location_code" {
+        let code = data.code.as_deref().map(str::trim).ok_or("code_required")?;
+        verify_code(event, user_id, code, now)?;
+    }
+    Ok(())
+}
+
+fn verify_code(event: &Event, user_id: i64, code: &str, now: i64) -> Result<(), &'static str> {
+    let mut attempts = CODE_ATTEMPTS.lock().unwrap();
+    attempts.retain(|_, (_, started)| now - *started < ATTEMPT_WINDOW_MS);
+    let (failures, _) = attempts.entry((event.id, user_id)).or_insert((0, now));
+    if *failures >= MAX_CODE_ATTEMPTS {
+        return Err("too_many_attempts");
+    }
+    let step = now.div_euclid(CODE_PERIOD_MS);
+    if [step, step - 1].iter().any(|step| code_at(&event.checkin_secret, *step).as_deref() == Some(code)) {
+        return Ok(());
+    }
+    *failures += 1;
+    Err("bad_code")
+}
+
+// the code on screen right now
+pub fn current_code(secret: &str, now: i64) -> Option<String> {
+    code_at(secret, now.div_euclid(CODE_PERIOD_MS))
+}
+
+// RFC 6238 style: HMAC-SHA1 over the time step, dynamically truncated to six digits
+fn code_at(secret: &str, step: i64) -> Option<String> {
+    if secret.is_empty() {
+        return None;
+    }
+    let key = PKey::hmac(secret.as_bytes()).ok()?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key).ok()?;
+    signer.update(&step.to_be_bytes()).ok()?;
+    let mac = signer.sign_to_vec().ok()?;
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([mac[offset] & 0x7f, mac[offset + 1], mac[offset + 2], mac[offset + 3]]);
+    Some(format!("{:0width$}", binary % 10u32.pow(CODE_DIGITS), width = CODE_DIGITS as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn officers_cannot_check_in_to_their_own_events() {
+        assert!(!self_checkin_allowed(false, true));
+    }
+
+    #[test]
+    fn students_and_admins_can_check_in() {
+        assert!(self_checkin_allowed(false, false));
+        assert!(self_checkin_allowed(true, true));
+        assert!(self_checkin_allowed(true, false));
+    }
+}
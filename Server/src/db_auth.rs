@@ -1,11 +1,15 @@
 use actix_web::{error, web, Error};
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+    Algorithm, Argon2, Params, Version, ARGON2ID_IDENT,
 };
-use rusqlite::{params, Statement};
+use chrono::Utc;
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::{params, params_from_iter, OptionalExtension, Row, Statement, TransactionBehavior};
 use serde::{Deserialize, Serialize};
-use std::str;
+use std::{collections::HashMap, env, str};
+
+use crate::paging;
 
 #[derive(Serialize)]
 pub struct UserPoints {
@@ -25,7 +29,6 @@ pub type Connection = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManage
 type PointQueryResult = Result<Vec<UserPoints>, rusqlite::Error>;
 
 pub enum AuthData {
-    GetUserScores,
     /* GetUserScoresTop, */
     GetCurrentUserScore,
 }
@@ -37,7 +40,6 @@ pub async fn execute_scores(pool: &Pool, query: AuthData, user_id: i64) -> Resul
 
     web::block(move || {
         match query {
-            AuthData::GetUserScores => get_user_scores(conn),
             /* AuthData::GetUserScoresTop => get_user_scores_top(conn), */
             AuthData::GetCurrentUserScore => get_current_user_score(conn, user_id)
         }
@@ -46,9 +48,48 @@ pub async fn execute_scores(pool: &Pool, query: AuthData, user_id: i64) -> Resul
     .map_err(error::ErrorInternalServerError)
 }
 
-fn get_user_scores(conn: Connection) -> PointQueryResult {
-    let stmt = conn.prepare("SELECT id, username, lifetime, score FROM users ORDER BY lifetime DESC LIMIT 105;")?;
-    get_score_rows(stmt)
+// how many users the public leaderboard shows
+pub const LEADERBOARD_TOP: usize = 105;
+
+pub const SCORE_SORTS: [paging::SortColumn; 3] = [
+    paging::SortColumn { name: "lifetime", column: "lifetime" },
+    paging::SortColumn { name: "score", column: "score" },
+    paging::SortColumn { name: "username", column: "username" },
+];
+
+// the leaderboard leaves out suspended and hidden users and names waiting on review
+pub async fn get_user_scores(pool: &Pool, page: paging::Page) -> Result<paging::Paged<UserPoints>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let listed = "suspended = 0 AND hidden = 0 AND id NOT IN (SELECT user_id FROM name_reviews WHERE status = 'pending')";
+        let total = conn.query_row(format!("SELECT COUNT(*) FROM users WHERE {};", listed).as_str(), [], |row| row.get::<_, i64>(0))?;
+        let (condition, order, values) = page.sql("id");
+        let mut stmt = conn.prepare(format!("SELECT id, username, lifetime, score FROM users WHERE {} AND {} {};", listed, condition, order).as_str())?;
+        let scores = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok(UserPoints {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    lifetime: row.get(2)?,
+                    score: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<UserPoints>, rusqlite::Error>>()?;
+        let key = |points: &UserPoints, sort: &str| {
+            let value = match sort {
+                "score" => paging::Key::Int(points.score),
+                "username" => paging::Key::Text(points.username.clone()),
+                _ => paging::Key::Int(points.lifetime),
+            };
+            (value, paging::Key::Int(points.id))
+        };
+        Ok::<paging::Paged<UserPoints>, rusqlite::Error>(page.finish(scores, total as usize, key))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
 }
 
 /*
@@ -86,9 +127,29 @@ pub struct User {
     pub lifetime: i64,
     pub score: i64,
     pub data: String,
+    pub cohort: String,
+    pub suspended: bool,
+    pub suspension_reason: String,
+    pub hidden: bool,
+}
+
+fn user_from_row(row: &Row) -> Result<User, rusqlite::Error> {
+    Ok(User {
+        id: row.get(0)?,
+        student_id: row.get(1)?,
+        username: row.get(2)?,
+        full_name: row.get(3)?,
+        pass_hash: row.get(4)?,
+        lifetime: row.get(5)?,
+        score: row.get(6)?,
+        data: row.get(7)?,
+        cohort: row.get(8)?,
+        suspended: row.get(9)?,
+        suspension_reason: row.get(10)?,
+        hidden: row.get(11)?,
+    })
 }
 
-/*
 pub async fn get_user_id(pool: &Pool, id: String) -> Result<User, Error> {
     let pool = pool.clone();
 
@@ -101,20 +162,28 @@ pub async fn get_user_id(pool: &Pool, id: String) -> Result<User, Error> {
 
 fn get_user_id_entry(conn: Connection, id: String) -> Result<User, rusqlite::Error> {
     let mut stmt = conn.prepare("SELECT * FROM users WHERE id=?1;")?;
-    stmt.query_row([id], |row| {
-        Ok(User {
-            id: row.get(0)?,
-            student_id: row.get(1)?,
-            username: row.get(2)?,
-            full_name: row.get(3)?,
-            pass_hash: row.get(4)?,
-            lifetime: row.get(5)?,
-            score: row.get(6)?,
-            data: row.get(7)?,
-        })
+    stmt.query_row([id], user_from_row)
+}
+
+// several users over one connection, keyed by id. ids without an account are left out
+pub async fn get_users(pool: &Pool, ids: Vec<i64>) -> Result<HashMap<i64, User>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut users = HashMap::new();
+        let mut stmt = conn.prepare("SELECT * FROM users WHERE id = ?1;")?;
+        for id in ids {
+            if let Some(user) = stmt.query_row(params![id], user_from_row).optional()? {
+                users.insert(id, user);
+            }
+        }
+        Ok::<HashMap<i64, User>, rusqlite::Error>(users)
     })
+    .await?
+    .map_err(error::ErrorInternalServerError)
 }
-*/
 
 pub async fn get_user_username(pool: &Pool, username: String) -> Result<User, Error> {
     let pool = pool.clone();
@@ -128,18 +197,7 @@ pub async fn get_user_username(pool: &Pool, username: String) -> Result<User, Er
 
 fn get_user_username_entry(conn: Connection, id: String) -> Result<User, rusqlite::Error> {
     let mut stmt = conn.prepare("SELECT * FROM users WHERE username=?1;")?;
-    stmt.query_row([id], |row| {
-        Ok(User {
-            id: row.get(0)?,
-            student_id: row.get(1)?,
-            username: row.get(2)?,
-            full_name: row.get(3)?,
-            pass_hash: row.get(4)?,
-            lifetime: row.get(5)?,
-            score: row.get(6)?,
-            data: row.get(7)?,
-        })
-    })
+    stmt.query_row([id], user_from_row)
 }
 
 pub async fn get_user_student_id(pool: &Pool, student_id: String) -> Result<User, Error> {
@@ -154,31 +212,26 @@ pub async fn get_user_student_id(pool: &Pool, student_id: String) -> Result<User
 
 fn get_user_student_id_entry(conn: Connection, id: String) -> Result<User, rusqlite::Error> {
     let mut stmt = conn.prepare("SELECT * FROM users WHERE student_id=?1;")?;
-    stmt.query_row([id], |row| {
-        Ok(User {
-            id: row.get(0)?,
-            student_id: row.get(1)?,
-            username: row.get(2)?,
-            full_name: row.get(3)?,
-            pass_hash: row.get(4)?,
-            lifetime: row.get(5)?,
-            score: row.get(6)?,
-            data: row.get(7)?,
-        })
-    })
+    stmt.query_row([id], user_from_row)
+}
+
+pub enum Signup {
+    Created(User),
+    InviteInvalid,
 }
 
-pub async fn create_user(pool: &Pool, student_id: String, full_name: String, username: String, password: String) -> Result<User, Error> {
+// an invite code, if given, is redeemed in the same transaction so a failed signup doesn't use it up
+pub async fn create_user(pool: &Pool, student_id: String, full_name: String, username: String, password: String, invite_code: String) -> Result<Signup, Error> {
     let pool = pool.clone();
-    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+    let mut conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
     web::block(move || {
         let generated_salt = SaltString::generate(&mut OsRng);
         // argon2id v19
-        let argon2ins = Argon2::default();
+        let argon2ins = password_hasher();
         // hash into phc string
         let hashed_password = argon2ins.hash_password(password.as_bytes(), &generated_salt);
         if hashed_password.is_err() {
-            return Ok(User {
+            return Ok(Signup::Created(User {
                 id: 0,
                 student_id,
                 username,
@@ -187,17 +240,33 @@ pub async fn create_user(pool: &Pool, student_id: String, full_name: String, use
                 lifetime: 0,
                 score: 0,
                 data: "".to_string(),
-            })
+                cohort: "".to_string(),
+                suspended: false,
+                suspension_reason: "".to_string(),
+                hidden: false,
+            }))
             .map_err(rusqlite::Error::NulError);
         }
-        create_user_entry(conn, student_id, username, full_name, hashed_password.unwrap().to_string())
+        let tx = conn.transaction()?;
+        // invite codes tag the account with a cohort
+        let cohort = if invite_code.is_empty() {
+            String::new()
+        } else {
+            match redeem_invite_sql(&tx, invite_code)? {
+                Some(cohort) => cohort,
+                None => return Ok(Signup::InviteInvalid),
+            }
+        };
+        let new_user = create_user_entry(&tx, student_id, username, full_name, hashed_password.unwrap().to_string(), cohort)?;
+        tx.commit()?;
+        Ok(Signup::Created(new_user))
     })
     .await?
     .map_err(error::ErrorInternalServerError)
 }
 
-fn create_user_entry(conn: Connection, student_id: String, username: String, full_name: String, password_hash: String) -> Result<User, rusqlite::Error> {
-    let mut stmt = conn.prepare("INSERT INTO users (student_id, username, full_name, pass_hash, lifetime, score, data) VALUES (?, ?, ?, ?, 0, 0, '');")?;
+fn create_user_entry(conn: &rusqlite::Connection, student_id: String, username: String, full_name: String, password_hash: String, cohort: String) -> Result<User, rusqlite::Error> {
+    let mut stmt = conn.prepare("INSERT INTO users (student_id, username, full_name, pass_hash, lifetime, score, data, cohort) VALUES (?, ?, ?, ?, 0, 0, '', ?);")?;
     let mut new_user = User {
         id: 0,
         student_id,
@@ -207,35 +276,175 @@ fn create_user_entry(conn: Connection, student_id: String, username: String, ful
         lifetime: 0,
         score: 0,
         data: "".to_string(),
+        cohort,
+        suspended: false,
+        suspension_reason: "".to_string(),
+        hidden: false,
     };
-    stmt.execute(params![new_user.student_id, new_user.username, new_user.full_name, new_user.pass_hash])?;
+    stmt.execute(params![new_user.student_id, new_user.username, new_user.full_name, new_user.pass_hash, new_user.cohort])?;
     new_user.id = conn.last_insert_rowid();
     Ok(new_user)
 }
 
-pub async fn update_points(pool: &Pool, user_id: i64, inc: i64) -> Result<bool, Error> {
+// argon2 costs come from ARGON2_MEMORY_KIB, ARGON2_TIME_COST and ARGON2_PARALLELISM, falling back to the crate defaults
+pub fn password_params() -> Params {
+    let cost = |key: &str, default: u32| env::var(key).ok().and_then(|value| value.parse::<u32>().ok()).unwrap_or(default);
+    Params::new(
+        cost("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+        cost("ARGON2_TIME_COST", Params::DEFAULT_T_COST),
+        cost("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        None,
+    )
+    .unwrap_or_default()
+}
+
+pub fn password_hasher() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, password_params())
+}
+
+// true if a stored hash was not made with the current algorithm, version and costs
+pub fn hash_outdated(hash: &PasswordHash) -> bool {
+    if hash.algorithm != ARGON2ID_IDENT || hash.version != Some(Version::V0x13.into()) {
+        return true;
+    }
+    match Params::try_from(hash) {
+        Ok(params) => {
+            let policy = password_params();
+            params.m_cost() != policy.m_cost() || params.t_cost() != policy.t_cost() || params.p_cost() != policy.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+// rehash a (verified) password with the current policy. returns the new phc string
+pub async fn update_password(pool: &Pool, user_id: i64, password: String) -> Result<String, Error> {
     let pool = pool.clone();
 
     let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
 
     web::block(move || {
-        update_points_sql(conn, user_id, inc)
+        let generated_salt = SaltString::generate(&mut OsRng);
+        let hashed_password = password_hasher()
+            .hash_password(password.as_bytes(), &generated_salt)
+            .map_err(|_| rusqlite::Error::InvalidQuery)?
+            .to_string();
+        let mut stmt = conn.prepare("UPDATE users SET pass_hash = ?1 WHERE id = ?2;")?;
+        stmt.execute(params![hashed_password, user_id])?;
+        Ok::<String, rusqlite::Error>(hashed_password)
     })
     .await?
     .map_err(error::ErrorInternalServerError)
 }
 
+#[derive(Serialize)]
+pub struct HashReport {
+    pub memory_kib: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+    pub current: i64,
+    pub legacy: i64,
+    pub unreadable: i64,
+}
+
+pub async fn get_hash_report(pool: &Pool) -> Result<HashReport, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || get_hash_report_sql(conn))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn get_hash_report_sql(conn: Connection) -> Result<HashReport, rusqlite::Error> {
+    let policy = password_params();
+    let mut report = HashReport {
+        memory_kib: policy.m_cost(),
+        time_cost: policy.t_cost(),
+        parallelism: policy.p_cost(),
+        current: 0,
+        legacy: 0,
+        unreadable: 0,
+    };
+    let mut stmt = conn.prepare("SELECT pass_hash FROM users;")?;
+    let hashes = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    for hash in hashes {
+        match PasswordHash::new(&hash) {
+            Ok(parsed) if hash_outdated(&parsed) => report.legacy += 1,
+            Ok(_) => report.current += 1,
+            Err(_) => report.unreadable += 1,
+        }
+    }
+    Ok(report)
+}
+
+pub async fn update_points(pool: &Pool, user_id: i64, inc: i64, reason: &str, reference: String) -> Result<bool, Error> {
+    let pool = pool.clone();
+    let reason = reason.to_string();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        update_points_sql(conn, user_id, inc, reason, reference)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
 
-pub fn update_points_sql(conn: Connection, user_id: i64, inc: i64) -> Result<bool, rusqlite::Error> {
-    let mut stmt = conn.prepare("UPDATE users SET score = score + ?1 WHERE id = ?2;")?;
-    stmt.execute(params![inc, user_id])?;
+pub fn update_points_sql(mut conn: Connection, user_id: i64, inc: i64, reason: String, reference: String) -> Result<bool, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    tx.execute("UPDATE users SET score = score + ?1 WHERE id = ?2;", params![inc, user_id])?;
     if inc > 0 {
-        let mut stmt_life = conn.prepare("UPDATE users SET lifetime = lifetime + ?1 WHERE id = ?2;")?;
-        stmt_life.execute(params![inc, user_id])?;
+        tx.execute("UPDATE users SET lifetime = lifetime + ?1 WHERE id = ?2;", params![inc, user_id])?;
     }
+    write_ledger(&tx, user_id, inc, &reason, &reference)?;
+    tx.commit()?;
     Ok(true)
 }
 
+// every point change is recorded with what caused it, so awards can be found and reversed later
+fn write_ledger(conn: &rusqlite::Connection, user_id: i64, amount: i64, reason: &str, reference: &str) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO point_ledger (user_id, amount, reason, reference, creation_date) VALUES (?1, ?2, ?3, ?4, ?5);",
+        params![user_id, amount, reason, reference, Utc::now().timestamp_millis()],
+    )?;
+    Ok(())
+}
+
+// takes back an award, score and lifetime both, since it was never really earned. awards from before the ledger existed use fallback_amount. returns the amount reversed, 0 if it already was
+pub async fn reverse_award(pool: &Pool, user_id: i64, reference: String, fallback_amount: i64) -> Result<i64, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || reverse_award_sql(conn, user_id, reference, fallback_amount))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn reverse_award_sql(mut conn: Connection, user_id: i64, reference: String, fallback_amount: i64) -> Result<i64, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let reversed: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM point_ledger WHERE user_id = ?1 AND reference = ?2 AND reason = 'reversal';",
+        params![user_id, reference],
+        |row| row.get(0),
+    )?;
+    if reversed > 0 {
+        return Ok(0);
+    }
+    let awarded: Option<i64> = tx
+        .query_row(
+            "SELECT SUM(amount) FROM point_ledger WHERE user_id = ?1 AND reference = ?2 AND reason = 'checkin';",
+            params![user_id, reference],
+            |row| row.get(0),
+        )?;
+    let amount = awarded.unwrap_or(fallback_amount);
+    tx.execute("UPDATE users SET score = score - ?1, lifetime = lifetime - ?1 WHERE id = ?2;", params![amount, user_id])?;
+    write_ledger(&tx, user_id, -amount, "reversal", &reference)?;
+    tx.commit()?;
+    Ok(amount)
+}
+
 pub async fn execute_manage_user(pool: &Pool, params: [String; 1]) -> Result<String, Error> {
     let pool = pool.clone();
 
@@ -260,3 +469,380 @@ fn execute_manage_action(mut statement: Statement, params: [String; 1]) -> Resul
         Ok("{\"status\":8002}".to_string())
     }
 }
+
+pub const INVITE_SORTS: [paging::SortColumn; 3] = [
+    paging::SortColumn { name: "creation_date", column: "creation_date" },
+    paging::SortColumn { name: "expires", column: "expires" },
+    paging::SortColumn { name: "code", column: "code" },
+];
+
+// invite codes are unique, so they break ties
+pub fn invite_sort_key(invite: &Invite, sort: &str) -> (paging::Key, paging::Key) {
+    let value = match sort {
+        "expires" => paging::Key::Int(invite.expires),
+        "code" => paging::Key::Text(invite.code.clone()),
+        _ => paging::Key::Int(invite.creation_date),
+    };
+    (value, paging::Key::Text(invite.code.clone()))
+}
+
+#[derive(Serialize, Clone)]
+pub struct Invite {
+    pub code: String,
+    pub cohort: String,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub expires: i64,
+    pub created_by: i64,
+    pub creation_date: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InviteCreateData {
+    pub cohort: String,
+    pub max_uses: i64,
+    pub expires: i64, // epoch millis, 0 for never
+}
+
+pub async fn create_invite(pool: &Pool, data: web::Json<InviteCreateData>, created_by: i64) -> Result<Invite, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || create_invite_sql(conn, &data, created_by))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn create_invite_sql(conn: Connection, data: &web::Json<InviteCreateData>, created_by: i64) -> Result<Invite, rusqlite::Error> {
+    let invite = Invite {
+        code: Alphanumeric.sample_string(&mut rand::thread_rng(), 10).to_uppercase(),
+        cohort: data.cohort.clone(),
+        max_uses: data.max_uses.max(1),
+        uses: 0,
+        expires: data.expires,
+        created_by,
+        creation_date: Utc::now().timestamp_millis(),
+    };
+    let mut stmt = conn.prepare("INSERT INTO invites (code, cohort, max_uses, uses, expires, created_by, creation_date) VALUES (?, ?, ?, 0, ?, ?, ?);")?;
+    stmt.execute(params![invite.code, invite.cohort, invite.max_uses, invite.expires, invite.created_by, invite.creation_date])?;
+    Ok(invite)
+}
+
+pub async fn get_invites(pool: &Pool) -> Result<Vec<Invite>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || get_invites_sql(conn))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn get_invites_sql(conn: Connection) -> Result<Vec<Invite>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM invites ORDER BY creation_date DESC;")?;
+    stmt.query_map([], |row| {
+        Ok(Invite {
+            code: row.get(0)?,
+            cohort: row.get(1)?,
+            max_uses: row.get(2)?,
+            uses: row.get(3)?,
+            expires: row.get(4)?,
+            created_by: row.get(5)?,
+            creation_date: row.get(6)?,
+        })
+    })
+    .and_then(Iterator::collect)
+}
+
+pub async fn delete_invite(pool: &Pool, code: String) -> Result<String, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let stmt = conn.prepare("DELETE FROM invites WHERE code=?1;")?;
+        execute_manage_action(stmt, [code])
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// uses up one redemption of an invite. returns the cohort for the new account, or None if the code is unknown, used up or expired
+fn redeem_invite_sql(conn: &rusqlite::Connection, code: String) -> Result<Option<String>, rusqlite::Error> {
+    // single statement so two signups can't both take the last use
+    let mut stmt = conn.prepare("UPDATE invites SET uses = uses + 1 WHERE code = ?1 AND uses < max_uses AND (expires = 0 OR expires > ?2) RETURNING cohort;")?;
+    let mut rows = stmt.query(params![code.to_uppercase(), Utc::now().timestamp_millis()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SuspendData {
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenameData {
+    pub username: String,
+    #[serde(default)]
+    pub override_filter: bool, // rename to a name the blocklist matches anyway
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HideData {
+    pub hidden: bool,
+}
+
+pub enum Moderation {
+    Suspend(String),
+    Unsuspend,
+    Rename(String),
+    Hide(bool),
+}
+
+pub async fn execute_moderation(pool: &Pool, action: Moderation, user_id: i64) -> Result<String, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = match action {
+            Moderation::Suspend(reason) => conn.execute("UPDATE users SET suspended = 1, suspension_reason = ?1 WHERE id = ?2;", params![reason, user_id])?,
+            Moderation::Unsuspend => conn.execute("UPDATE users SET suspended = 0, suspension_reason = '' WHERE id = ?1;", params![user_id])?,
+            Moderation::Rename(username) => conn.execute("UPDATE users SET username = ?1 WHERE id = ?2;", params![username, user_id])?,
+            Moderation::Hide(hidden) => conn.execute("UPDATE users SET hidden = ?1 WHERE id = ?2;", params![hidden, user_id])?,
+        };
+        if changed == 1 {
+            Ok::<String, rusqlite::Error>("{\"status\":3206}".to_string())
+        } else {
+            Ok("{\"status\":8002}".to_string())
+        }
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// a new name replaces whatever was still waiting for review
+pub async fn update_profile(pool: &Pool, user_id: i64, username: String, full_name: String) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || update_profile_sql(conn, user_id, username, full_name))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn update_profile_sql(mut conn: Connection, user_id: i64, username: String, full_name: String) -> Result<bool, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    tx.execute("UPDATE users SET username = ?1, full_name = ?2 WHERE id = ?3;", params![username, full_name, user_id])?;
+    tx.execute("UPDATE name_reviews SET status = 'superseded' WHERE user_id = ?1 AND status = 'pending';", params![user_id])?;
+    tx.commit()?;
+    Ok(true)
+}
+
+#[derive(Serialize, Clone)]
+pub struct NameReview {
+    pub id: i64,
+    pub user_id: i64,
+    pub field: String,
+    pub value: String,
+    pub matched: String,
+    pub status: String,
+    pub creation_date: i64,
+}
+
+pub const NAME_REVIEW_SORTS: [paging::SortColumn; 3] = [
+    paging::SortColumn { name: "creation_date", column: "creation_date" },
+    paging::SortColumn { name: "status", column: "status" },
+    paging::SortColumn { name: "id", column: "id" },
+];
+
+pub fn name_review_sort_key(review: &NameReview, sort: &str) -> (paging::Key, paging::Key) {
+    let value = match sort {
+        "status" => paging::Key::Text(review.status.clone()),
+        "id" => paging::Key::Int(review.id),
+        _ => paging::Key::Int(review.creation_date),
+    };
+    (value, paging::Key::Int(review.id))
+}
+
+pub async fn create_name_review(pool: &Pool, user_id: i64, field: String, value: String, matched: String) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("INSERT INTO name_reviews (user_id, field, value, matched, status, creation_date) VALUES (?, ?, ?, ?, 'pending', ?);")?;
+        stmt.execute(params![user_id, field, value, matched, Utc::now().timestamp_millis()])?;
+        Ok::<bool, rusqlite::Error>(true)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_name_reviews(pool: &Pool) -> Result<Vec<NameReview>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || get_name_reviews_sql(conn))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn get_name_reviews_sql(conn: Connection) -> Result<Vec<NameReview>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM name_reviews WHERE status = 'pending' ORDER BY creation_date ASC;")?;
+    stmt.query_map([], |row| {
+        Ok(NameReview {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            field: row.get(2)?,
+            value: row.get(3)?,
+            matched: row.get(4)?,
+            status: row.get(5)?,
+            creation_date: row.get(6)?,
+        })
+    })
+    .and_then(Iterator::collect)
+}
+
+// a rejected name keeps the account off the leaderboard until an admin renames it and unhides it
+pub async fn resolve_name_review(pool: &Pool, review_id: i64, approved: bool) -> Result<String, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || resolve_name_review_sql(conn, review_id, approved))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn resolve_name_review_sql(mut conn: Connection, review_id: i64, approved: bool) -> Result<String, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let changed = tx.execute(
+        "UPDATE name_reviews SET status = ?1 WHERE id = ?2 AND status = 'pending';",
+        params![if approved { "approved" } else { "rejected" }, review_id],
+    )?;
+    if changed == 0 {
+        return Ok("{\"status\":8002}".to_string());
+    }
+    if !approved {
+        tx.execute("UPDATE users SET hidden = 1 WHERE id = (SELECT user_id FROM name_reviews WHERE id = ?1);", params![review_id])?;
+    }
+    tx.commit()?;
+    Ok("{\"status\":3206}".to_string())
+}
+
+#[derive(Serialize, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor_id: i64,
+    pub subject_id: i64,
+    pub action: String,
+    pub detail: String,
+    pub creation_date: i64,
+}
+
+pub async fn write_audit(pool: &Pool, actor_id: i64, subject_id: i64, action: &str, detail: String) -> Result<bool, Error> {
+    let pool = pool.clone();
+    let action = action.to_string();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("INSERT INTO audit_log (actor_id, subject_id, action, detail, creation_date) VALUES (?, ?, ?, ?, ?);")?;
+        stmt.execute(params![actor_id, subject_id, action, detail, Utc::now().timestamp_millis()])?;
+        Ok::<bool, rusqlite::Error>(true)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub const AUDIT_SORTS: [paging::SortColumn; 2] = [
+    paging::SortColumn { name: "id", column: "id" },
+    paging::SortColumn { name: "creation_date", column: "creation_date" },
+];
+
+pub async fn get_audit_log(pool: &Pool, page: paging::Page) -> Result<paging::Paged<AuditEntry>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || get_audit_log_sql(conn, page))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn get_audit_log_sql(conn: Connection, page: paging::Page) -> Result<paging::Paged<AuditEntry>, rusqlite::Error> {
+    let total = conn.query_row("SELECT COUNT(*) FROM audit_log;", [], |row| row.get::<_, i64>(0))?;
+    let (condition, order, values) = page.sql("id");
+    let mut stmt = conn.prepare(format!("SELECT * FROM audit_log WHERE {} {};", condition, order).as_str())?;
+    let entries = stmt
+        .query_map(params_from_iter(values), |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                actor_id: row.get(1)?,
+                subject_id: row.get(2)?,
+                action: row.get(3)?,
+                detail: row.get(4)?,
+                creation_date: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<AuditEntry>, rusqlite::Error>>()?;
+    let key = |entry: &AuditEntry, sort: &str| {
+        let value = if sort == "creation_date" { paging::Key::Int(entry.creation_date) } else { paging::Key::Int(entry.id) };
+        (value, paging::Key::Int(entry.id))
+    };
+    Ok(page.finish(entries, total as usize, key))
+}
+
+// private calendar feed tokens. reading never creates one, they're only made by POST /api/v1/user/calendar. rotating one invalidates the old feed url
+// the user's feed token, if they've made one
+pub async fn get_calendar_token(pool: &Pool, user_id: i64) -> Result<Option<String>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.query_row("SELECT calendar_token FROM users WHERE id = ?1;", params![user_id], |row| row.get::<_, String>(0))
+            .map(|token| Some(token).filter(|token| !token.is_empty()))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// makes the user's feed token, or replaces it when rotate is set so the old feed url stops working
+pub async fn create_calendar_token(pool: &Pool, user_id: i64, rotate: bool) -> Result<String, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || -> Result<String, rusqlite::Error> {
+        let token: String = conn.query_row("SELECT calendar_token FROM users WHERE id = ?1;", params![user_id], |row| row.get(0))?;
+        if !token.is_empty() && !rotate {
+            return Ok(token);
+        }
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 40);
+        conn.execute("UPDATE users SET calendar_token = ?1 WHERE id = ?2;", params![token, user_id])?;
+        Ok(token)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_calendar_owner(pool: &Pool, token: String) -> Result<Option<i64>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.query_row("SELECT id FROM users WHERE calendar_token = ?1 AND calendar_token != '';", params![token], |row| row.get(0))
+            .optional()
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
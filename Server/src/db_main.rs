@@ -1,19 +1,205 @@
 use actix_web::{error, web, Error};
-use rusqlite::{params, Statement};
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::{params, params_from_iter, OptionalExtension, Row, Statement, TransactionBehavior};
 use serde::{Serialize, Deserialize};
+use std::env;
+
+use crate::{checkin, geo, markdown, paging, recurrence, survey, timezone};
+
+// how far ahead recurring events are expanded for listings
+const RECURRENCE_HORIZON: i64 = 366 * 86_400_000;
 
 #[derive(Serialize, Clone)]
 pub struct Event {
     pub id: i64,
     pub start_time: i64,
     pub end_time: i64,
+    pub timezone: String, // IANA zone the event happens in
+    pub start_local: String, // start_time and end_time as local RFC 3339 times in that zone
+    pub end_local: String,
     pub title: String,
     pub human_location: String,
     pub latitude: f64,
     pub longitude: f64,
-    pub details: String,
-    pub image: String, // 640x320 or 1280x640
+    pub details: String, // markdown
+    pub details_html: String, // details rendered and sanitized
+    pub details_text: String, // details without formatting, for passes and calendars
+    pub image: String, // url of an uploaded 1280x640 image
     pub point_reward: i64,
+    pub capacity: Option<i64>, // none for unlimited
+    pub rsvp_cutoff: Option<i64>, // none to allow cancelling until start_time
+    pub rrule: String, // empty for one-off events
+    pub exdates: Vec<i64>, // start times of cancelled occurrences
+    pub occurrence: i64, // start time of this occurrence of a recurring event, 0 otherwise
+    pub uid: String, // iCalendar UID of imported events, empty otherwise
+    pub category: String, // one of CATEGORIES, or empty
+    pub tags: Vec<String>,
+    pub status: String, // one of STATUSES
+    pub publish_at: Option<i64>, // when a scheduled event becomes published
+    pub deleted_at: Option<i64>, // set while the event is in the trash
+    pub self_checkin: String, // one of checkin::MODES
+    pub checkin_radius: Option<i64>, // meters around latitude/longitude, none for the server default
+    #[serde(skip_serializing)]
+    pub checkin_secret: String, // key for rotating check-in codes, empty until first shown
+    pub checkin_opens_before: Option<i64>, // minutes before start_time check-in opens
+    pub checkin_closes_after: Option<i64>, // minutes after end_time check-in closes
+    pub organization_id: Option<i64>, // the club running the event, none for school events
+}
+
+impl Event {
+    pub fn rsvp_cutoff(&self) -> i64 {
+        self.rsvp_cutoff.unwrap_or(self.start_time)
+    }
+
+    // whether students can see the event. scheduled events publish themselves once publish_at passes
+    pub fn published(&self, now: i64) -> bool {
+        self.status == "published" || (self.status == "scheduled" && self.publish_at.is_some_and(|publish_at| publish_at <= now))
+    }
+
+    pub fn recurring(&self) -> bool {
+        !self.rrule.is_empty()
+    }
+
+    // occurrence start times through horizon. empty for one-off events or unreadable rules
+    pub fn occurrences(&self, horizon: i64) -> Vec<i64> {
+        match recurrence::parse(&self.rrule) {
+            Ok(rule) if self.recurring() => recurrence::occurrences(&rule, self.start_time, &self.exdates, horizon, &timezone::zone(&self.timezone)),
+            _ => Vec::new(),
+        }
+    }
+
+    // a copy of a recurring event moved to one of its occurrences
+    pub fn at_occurrence(&self, occurrence: i64) -> Event {
+        let offset = occurrence - self.start_time;
+        let mut event = self.clone();
+        event.start_time += offset;
+        event.end_time += offset;
+        let zone = timezone::zone(&self.timezone);
+        event.start_local = timezone::local_string(&zone, event.start_time);
+        event.end_local = timezone::local_string(&zone, event.end_time);
+        event.rsvp_cutoff = self.rsvp_cutoff.map(|cutoff| cutoff + offset);
+        event.occurrence = occurrence;
+        event
+    }
+
+    // picks the occurrence a request is about. one-off events are always themselves. without an explicit occurrence, a recurring event resolves to the one running now or the next one
+    pub fn resolve_occurrence(&self, requested: Option<i64>, now: i64) -> Option<Event> {
+        if !self.recurring() {
+            return Some(self.clone());
+        }
+        let duration = self.end_time - self.start_time;
+        match requested {
+            Some(occurrence) => self.occurrences(occurrence).contains(&occurrence).then(|| self.at_occurrence(occurrence)),
+            None => self
+                .occurrences(now + RECURRENCE_HORIZON)
+                .into_iter()
+                .find(|occurrence| occurrence + duration > now)
+                .map(|occurrence| self.at_occurrence(occurrence)),
+        }
+    }
+}
+
+fn join_exdates(exdates: &[i64]) -> String {
+    exdates.iter().map(i64::to_string).collect::<Vec<String>>().join(",")
+}
+
+fn split_exdates(exdates: String) -> Vec<i64> {
+    exdates.split(',').filter_map(|exdate| exdate.trim().parse::<i64>().ok()).collect()
+}
+
+pub const CATEGORIES: [&str; 5] = ["sports", "arts", "clubs", "academics", "wellness"];
+
+pub const STATUSES: [&str; 5] = ["draft", "scheduled", "published", "cancelled", "archived"];
+
+fn default_status() -> String {
+    "published".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct EventStatusData {
+    pub status: String,
+    pub publish_at: Option<i64>,
+}
+
+// checks a status change or a new event's status. scheduled events need a publish time
+pub fn validate_status(status: &str, publish_at: Option<i64>) -> Result<(), &'static str> {
+    if !STATUSES.contains(&status) {
+        return Err("bad_status");
+    }
+    if status == "scheduled" && publish_at.is_none() {
+        return Err("publish_at_required");
+    }
+    Ok(())
+}
+
+pub async fn update_event_status(pool: &Pool, event_id: i64, data: EventStatusData) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        // publish_at only means something while scheduled
+        let publish_at = if data.status == "scheduled" { data.publish_at } else { None };
+        let changed = conn.execute("UPDATE events SET status = ?1, publish_at = ?2 WHERE id = ?3;", params![data.status, publish_at, event_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// tags are stored comma separated, lowercase and without duplicates
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.iter().map(|tag| tag.trim().replace(',', " ").to_lowercase()) {
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+fn split_tags(tags: String) -> Vec<String> {
+    tags.split(',').filter(|tag| !tag.is_empty()).map(str::to_string).collect()
+}
+
+// query parameters for /api/v1/events/future. every given filter has to match
+#[derive(Deserialize)]
+pub struct EventFilter {
+    pub category: Option<String>,
+    pub tag: Option<String>,
+    pub from: Option<i64>, // epoch millis, events ending after
+    pub to: Option<i64>, // epoch millis, events starting before
+    pub q: Option<String>,
+    pub lat: Option<f64>,
+    pub long: Option<f64>,
+    pub radius: Option<f64>, // meters, defaults to 5km
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        if self.category.as_ref().is_some_and(|category| !event.category.eq_ignore_ascii_case(category)) {
+            return false;
+        }
+        if self.tag.as_ref().is_some_and(|tag| !event.tags.contains(&tag.trim().to_lowercase())) {
+            return false;
+        }
+        if self.from.is_some_and(|from| event.end_time <= from) || self.to.is_some_and(|to| event.start_time >= to) {
+            return false;
+        }
+        if let Some(q) = self.q.as_ref().map(|q| q.trim().to_lowercase()).filter(|q| !q.is_empty()) {
+            let found = [&event.title, &event.details_text, &event.human_location].iter().any(|field| field.to_lowercase().contains(&q))
+                || event.tags.iter().any(|tag| tag.contains(&q));
+            if !found {
+                return false;
+            }
+        }
+        if let (Some(lat), Some(long)) = (self.lat, self.long) {
+            if geo::distance_m(lat, long, event.latitude, event.longitude) > self.radius.unwrap_or(5_000.0) {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -22,13 +208,15 @@ pub struct Ticket {
     pub event_id: i64,
     pub holder_id: i64,
     pub creation_date: i64,
+    pub status: String, // reserved, checked_in or void
+    pub checkin_date: i64, // 0 until checked in
+    pub occurrence: i64, // occurrence start time for recurring events, 0 otherwise
 }
 
 pub type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
 pub type Connection = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
 
 pub enum EventQuery {
-    GetAllEvents,
     GetFutureEvents,
     GetEventById
 }
@@ -40,7 +228,6 @@ pub async fn execute_events(pool: &Pool, query: EventQuery, unix_time: u128) ->
 
     web::block(move || {
         match query {
-            EventQuery::GetAllEvents => get_all_events(conn),
             EventQuery::GetFutureEvents => get_future_events(conn, unix_time),
             EventQuery::GetEventById => get_event_by_id(conn, unix_time as i64)
         }
@@ -49,44 +236,146 @@ pub async fn execute_events(pool: &Pool, query: EventQuery, unix_time: u128) ->
     .map_err(error::ErrorInternalServerError)
 }
 
-fn get_all_events(conn: Connection) -> Result<Vec<Event>, rusqlite::Error> {
-    let stmt = conn.prepare("SELECT * FROM events ORDER BY start_time DESC;")?;
-    get_event_rows(stmt)
+pub const EVENT_SORTS: [paging::SortColumn; 4] = [
+    paging::SortColumn { name: "start_time", column: "start_time" },
+    paging::SortColumn { name: "end_time", column: "end_time" },
+    paging::SortColumn { name: "title", column: "title" },
+    paging::SortColumn { name: "id", column: "id" },
+];
+
+pub fn event_sort_key(event: &Event, sort: &str) -> paging::Key {
+    match sort {
+        "end_time" => paging::Key::Int(event.end_time),
+        "title" => paging::Key::Text(event.title.clone()),
+        "id" => paging::Key::Int(event.id),
+        _ => paging::Key::Int(event.start_time),
+    }
+}
+
+// every event outside the trash, a page at a time
+pub async fn get_all_events(pool: &Pool, page: paging::Page) -> Result<paging::Paged<Event>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let total = conn.query_row("SELECT COUNT(*) FROM events WHERE deleted_at IS NULL;", [], |row| row.get::<_, i64>(0))?;
+        let (condition, order, values) = page.sql("id");
+        let mut stmt = conn.prepare(format!("SELECT * FROM events WHERE deleted_at IS NULL AND {} {};", condition, order).as_str())?;
+        let events = stmt.query_map(params_from_iter(values), event_from_row)?.collect::<Result<Vec<Event>, rusqlite::Error>>()?;
+        Ok::<paging::Paged<Event>, rusqlite::Error>(page.finish(events, total as usize, |event, sort| (event_sort_key(event, sort), paging::Key::Int(event.id))))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
 }
 
 fn get_future_events(conn: Connection, unix_time: u128) -> Result<Vec<Event>, rusqlite::Error> {
-    let stmt = conn.prepare(format!("SELECT * FROM events WHERE end_time > {} ORDER BY start_time DESC;", unix_time).as_str())?;
-    get_event_rows(stmt)
+    let now = unix_time as i64;
+    let stmt = conn.prepare(
+        format!(
+            "SELECT * FROM events WHERE deleted_at IS NULL AND (end_time > {0} OR rrule != '') AND (status = 'published' OR (status = 'scheduled' AND publish_at <= {0})) ORDER BY start_time DESC;",
+            unix_time
+        )
+        .as_str(),
+    )?;
+    // recurring events are listed once per upcoming occurrence
+    let mut events: Vec<Event> = get_event_rows(stmt)?
+        .into_iter()
+        .flat_map(|event| {
+            if event.recurring() {
+                let duration = event.end_time - event.start_time;
+                event
+                    .occurrences(now + RECURRENCE_HORIZON)
+                    .into_iter()
+                    .filter(|occurrence| occurrence + duration > now)
+                    .map(|occurrence| event.at_occurrence(occurrence))
+                    .collect()
+            } else {
+                vec![event]
+            }
+        })
+        .collect();
+    events.sort_by_key(|event| std::cmp::Reverse(event.start_time));
+    Ok(events)
 }
 
 fn get_event_by_id(conn: Connection, event_id: i64) -> Result<Vec<Event>, rusqlite::Error> {
-    let stmt = conn.prepare(format!("SELECT * FROM events WHERE id = {};", event_id).as_str())?;
+    let stmt = conn.prepare(format!("SELECT * FROM events WHERE id = {} AND deleted_at IS NULL;", event_id).as_str())?;
     get_event_rows(stmt)
 }
 
 fn get_event_rows(mut statement: Statement) -> Result<Vec<Event>, rusqlite::Error> {
     statement
-        .query_map([], |row| {
-            Ok(Event {
-                id: row.get(0)?,
-                start_time: row.get(1)?,
-                end_time: row.get(2)?,
-                title: row.get(3)?,
-                human_location: row.get(4)?,
-                latitude: row.get(5)?,
-                longitude: row.get(6)?,
-                details: row.get(7)?,
-                image: row.get(8)?,
-                point_reward: row.get(9)?,
-            })
-        })
+        .query_map([], event_from_row)
         .and_then(Iterator::collect)
 }
 
+fn event_from_row(row: &Row) -> Result<Event, rusqlite::Error> {
+    // events stored without a zone follow the deployment's
+    let zone = timezone::zone(&row.get::<_, String>(26)?);
+    let (start_time, end_time): (i64, i64) = (row.get(1)?, row.get(2)?);
+    Ok(Event {
+        id: row.get(0)?,
+        start_time,
+        end_time,
+        timezone: zone.name().to_string(),
+        start_local: timezone::local_string(&zone, start_time),
+        end_local: timezone::local_string(&zone, end_time),
+        title: row.get(3)?,
+        human_location: row.get(4)?,
+        latitude: row.get(5)?,
+        longitude: row.get(6)?,
+        details: row.get(7)?,
+        details_html: row.get(27)?,
+        details_text: row.get(28)?,
+        image: row.get(8)?,
+        point_reward: row.get(9)?,
+        capacity: row.get(10)?,
+        rsvp_cutoff: row.get(11)?,
+        rrule: row.get(12)?,
+        exdates: split_exdates(row.get(13)?),
+        occurrence: 0,
+        uid: row.get(14)?,
+        category: row.get(15)?,
+        tags: split_tags(row.get(16)?),
+        status: row.get(17)?,
+        publish_at: row.get(18)?,
+        deleted_at: row.get(19)?,
+        self_checkin: row.get(20)?,
+        checkin_radius: row.get(21)?,
+        checkin_secret: row.get(22)?,
+        checkin_opens_before: row.get(23)?,
+        checkin_closes_after: row.get(24)?,
+        organization_id: row.get(25)?,
+    })
+}
+
+// events a user holds a reservation or ticket for, at the occurrence the ticket is for
+pub async fn get_held_events(pool: &Pool, user_id: i64) -> Result<Vec<Event>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare(
+            "SELECT events.*, tickets.occurrence FROM tickets JOIN events ON events.id = tickets.event_id AND events.deleted_at IS NULL
+            WHERE tickets.holder_id = ?1 AND tickets.status IN ('reserved', 'checked_in') ORDER BY events.start_time DESC;",
+        )?;
+        let events = stmt
+            .query_map(params![user_id], |row| {
+                let event = event_from_row(row)?;
+                let occurrence: i64 = row.get("occurrence")?;
+                Ok(if occurrence == 0 { event } else { event.at_occurrence(occurrence) })
+            })?
+            .collect::<Result<Vec<Event>, rusqlite::Error>>()?;
+        Ok::<Vec<Event>, rusqlite::Error>(events)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
 pub enum TicketQuery {
-    GetAllTickets,
     GetTicketById,
-    GetUserEventTickets
 }
 
 pub async fn execute_tickets(pool: &Pool, query: TicketQuery, parameter: String) -> Result<Vec<Ticket>, Error> {
@@ -96,18 +385,43 @@ pub async fn execute_tickets(pool: &Pool, query: TicketQuery, parameter: String)
 
     web::block(move || {
         match query {
-            TicketQuery::GetAllTickets => get_all_tickets(conn),
             TicketQuery::GetTicketById => get_ticket_id(conn, parameter),
-            TicketQuery::GetUserEventTickets => get_user_event_tickets(conn, parameter),
         }
     })
     .await?
     .map_err(error::ErrorInternalServerError)
 }
 
-fn get_all_tickets(conn: Connection) -> Result<Vec<Ticket>, rusqlite::Error> {
-    let stmt = conn.prepare("SELECT * FROM events ORDER BY start_time DESC;")?;
-    get_ticket_rows(stmt)
+pub const TICKET_SORTS: [paging::SortColumn; 4] = [
+    paging::SortColumn { name: "creation_date", column: "creation_date" },
+    paging::SortColumn { name: "checkin_date", column: "checkin_date" },
+    paging::SortColumn { name: "event_id", column: "event_id" },
+    paging::SortColumn { name: "id", column: "id" },
+];
+
+fn ticket_sort_key(ticket: &Ticket, sort: &str) -> paging::Key {
+    match sort {
+        "checkin_date" => paging::Key::Int(ticket.checkin_date),
+        "event_id" => paging::Key::Int(ticket.event_id),
+        "id" => paging::Key::Int(ticket.id),
+        _ => paging::Key::Int(ticket.creation_date),
+    }
+}
+
+pub async fn get_all_tickets(pool: &Pool, page: paging::Page) -> Result<paging::Paged<Ticket>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let total = conn.query_row("SELECT COUNT(*) FROM tickets;", [], |row| row.get::<_, i64>(0))?;
+        let (condition, order, values) = page.sql("id");
+        let mut stmt = conn.prepare(format!("SELECT * FROM tickets WHERE {} {};", condition, order).as_str())?;
+        let tickets = stmt.query_map(params_from_iter(values), ticket_from_row)?.collect::<Result<Vec<Ticket>, rusqlite::Error>>()?;
+        Ok::<paging::Paged<Ticket>, rusqlite::Error>(page.finish(tickets, total as usize, |ticket, sort| (ticket_sort_key(ticket, sort), paging::Key::Int(ticket.id))))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
 }
 
 fn get_ticket_id(conn: Connection, ticket_id: String) -> Result<Vec<Ticket>, rusqlite::Error> {
@@ -115,48 +429,143 @@ fn get_ticket_id(conn: Connection, ticket_id: String) -> Result<Vec<Ticket>, rus
     get_ticket_rows(stmt)
 }
 
-fn get_user_event_tickets(conn: Connection, user_event: String) -> Result<Vec<Ticket>, rusqlite::Error> {
-    let user_event_data: Vec<&str> = user_event.split("_").collect();
-    let stmt = conn.prepare(format!("SELECT * FROM tickets WHERE holder_id={} AND event_id={}", user_event_data[0], user_event_data[1]).as_str())?;
-    get_ticket_rows(stmt)
+// every ticket for an event, or for one occurrence of it
+pub async fn get_event_tickets(pool: &Pool, event_id: i64, occurrence: Option<i64>) -> Result<Vec<Ticket>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("SELECT * FROM tickets WHERE event_id = ?1 AND (?2 IS NULL OR occurrence = ?2) ORDER BY creation_date ASC;")?;
+        let tickets = stmt.query_map(params![event_id, occurrence], ticket_from_row)?.collect::<Result<Vec<Ticket>, rusqlite::Error>>()?;
+        Ok::<Vec<Ticket>, rusqlite::Error>(tickets)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+fn ticket_from_row(row: &Row) -> Result<Ticket, rusqlite::Error> {
+    Ok(Ticket {
+        id: row.get(0)?,
+        event_id: row.get(1)?,
+        holder_id: row.get(2)?,
+        creation_date: row.get(3)?,
+        status: row.get(4)?,
+        checkin_date: row.get(5)?,
+        occurrence: row.get(6)?,
+    })
 }
 
 fn get_ticket_rows(mut statement: Statement) -> Result<Vec<Ticket>, rusqlite::Error> {
     statement
-        .query_map([], |row| {
-            Ok(Ticket {
-                id: row.get(0)?,
-                event_id: row.get(1)?,
-                holder_id: row.get(2)?,
-                creation_date: row.get(3)?,
-            })
-        })
+        .query_map([], ticket_from_row)
         .and_then(Iterator::collect)
 }
 
-pub async fn create_ticket(pool: &Pool, event_id: i64, user_id: i64, creation_date: u128) -> Result<Ticket, Error> {
+pub enum Admission {
+    Issued(Ticket),
+    AlreadyHeld,
+    Full,
+}
+
+// student-facing reservation. does not award points
+pub async fn reserve_ticket(pool: &Pool, event: &Event, user_id: i64, now: i64) -> Result<Admission, Error> {
+    admit(pool, event, user_id, now, "reserved").await
+}
+
+// door check-in. turns a reservation into a checked in ticket, or issues one if there is room
+pub async fn check_in(pool: &Pool, event: &Event, user_id: i64, now: i64) -> Result<Admission, Error> {
+    admit(pool, event, user_id, now, "checked_in").await
+}
+
+async fn admit(pool: &Pool, event: &Event, user_id: i64, now: i64, status: &'static str) -> Result<Admission, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence, capacity) = (event.id, event.occurrence, event.capacity);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || admit_sql(conn, event_id, occurrence, capacity, user_id, now, status))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn admit_sql(mut conn: Connection, event_id: i64, occurrence: i64, capacity: Option<i64>, user_id: i64, now: i64, status: &str) -> Result<Admission, rusqlite::Error> {
+    // immediate so the seat count can't change between counting and inserting
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    match held_ticket(&tx, event_id, occurrence, user_id)? {
+        Some(mut ticket) if ticket.status == "reserved" && status == "checked_in" => {
+            tx.execute("UPDATE tickets SET status = 'checked_in', checkin_date = ?1 WHERE id = ?2;", params![now, ticket.id])?;
+            tx.commit()?;
+            ticket.status = "checked_in".to_string();
+            ticket.checkin_date = now;
+            Ok(Admission::Issued(ticket))
+        }
+        Some(_) => Ok(Admission::AlreadyHeld),
+        None => {
+            if let Some(capacity) = capacity {
+                if seats_taken(&tx, event_id, occurrence)? >= capacity {
+                    return Ok(Admission::Full);
+                }
+            }
+            let ticket = insert_ticket(&tx, event_id, occurrence, user_id, now, status)?;
+            tx.commit()?;
+            Ok(Admission::Issued(ticket))
+        }
+    }
+}
+
+fn held_ticket(conn: &rusqlite::Connection, event_id: i64, occurrence: i64, user_id: i64) -> Result<Option<Ticket>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT * FROM tickets WHERE holder_id = ?1 AND event_id = ?2 AND occurrence = ?3 AND status IN ('reserved', 'checked_in');",
+        params![user_id, event_id, occurrence],
+        ticket_from_row,
+    )
+    .optional()
+}
+
+fn seats_taken(conn: &rusqlite::Connection, event_id: i64, occurrence: i64) -> Result<i64, rusqlite::Error> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM tickets WHERE event_id = ?1 AND occurrence = ?2 AND status IN ('reserved', 'checked_in');",
+        params![event_id, occurrence],
+        |row| row.get(0),
+    )
+}
+
+fn insert_ticket(conn: &rusqlite::Connection, event_id: i64, occurrence: i64, user_id: i64, now: i64, status: &str) -> Result<Ticket, rusqlite::Error> {
+    let mut ticket = Ticket {
+        id: 0,
+        event_id,
+        holder_id: user_id,
+        creation_date: now,
+        status: status.to_string(),
+        checkin_date: if status == "checked_in" { now } else { 0 },
+        occurrence,
+    };
+    // the id is the rowid. one made up from the time and ids collided once recurring events gave a user a ticket every week
+    conn.execute(
+        "INSERT INTO tickets (event_id, holder_id, creation_date, status, checkin_date, occurrence) VALUES (?, ?, ?, ?, ?, ?);",
+        params![ticket.event_id, ticket.holder_id, ticket.creation_date, ticket.status, ticket.checkin_date, ticket.occurrence],
+    )?;
+    ticket.id = conn.last_insert_rowid();
+    Ok(ticket)
+}
+
+pub async fn cancel_reservation(pool: &Pool, event: &Event, user_id: i64) -> Result<bool, Error> {
     let pool = pool.clone();
+    let (event_id, occurrence) = (event.id, event.occurrence);
 
     let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
 
     web::block(move || {
-        get_ticket_sql(conn, event_id, user_id, creation_date)
+        let changed = conn.execute(
+            "DELETE FROM tickets WHERE holder_id = ?1 AND event_id = ?2 AND occurrence = ?3 AND status = 'reserved';",
+            params![user_id, event_id, occurrence],
+        )?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
     })
     .await?
     .map_err(error::ErrorInternalServerError)
 }
-
-fn get_ticket_sql(conn: Connection, event_id: i64, user_id: i64, creation_date: u128) -> Result<Ticket, rusqlite::Error> {
-    let ticket_id: i64 = format!("{}{}{}", (creation_date % 1000).to_string(), format!("{:0>6}", event_id.to_string()), format!("{:0>7}", user_id.to_string())).parse::<i64>().unwrap();
-    let mut stmt = conn.prepare("INSERT INTO tickets (id, event_id, holder_id, creation_date) VALUES (?, ?, ?, ?)")?;
-    stmt.execute(params![
-        ticket_id,
-        event_id,
-        user_id,
-        creation_date as i64
-    ])?;
-    Ok(Ticket { id: ticket_id, event_id, holder_id: user_id, creation_date: creation_date as i64 })
-}
 /*
 pub async fn expend_ticket(pool: &Pool, ticket_id: String) -> Result<bool, Error> {
     let pool = pool.clone();
@@ -177,34 +586,152 @@ fn expend_ticket_sql(conn: Connection, ticket_id: String) -> Result<bool, rusqli
 }
 */
 
-pub async fn delete_event(pool: &Pool, params: String) -> Result<String, Error> {
+pub async fn set_event_image(pool: &Pool, event_id: i64, image: String) -> Result<bool, Error> {
     let pool = pool.clone();
 
     let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
 
     web::block(move || {
-        delete_event_sql(conn, params)
+        let changed = conn.execute("UPDATE events SET image = ?1 WHERE id = ?2 AND deleted_at IS NULL;", params![image, event_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn set_self_checkin(pool: &Pool, event_id: i64, settings: checkin::SelfCheckinSettings) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute(
+            "UPDATE events SET self_checkin = ?1, checkin_radius = ?2 WHERE id = ?3 AND deleted_at IS NULL;",
+            params![settings.mode, settings.radius, event_id],
+        )?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
     })
     .await?
     .map_err(error::ErrorInternalServerError)
 }
 
-fn delete_event_sql(connection: Connection, params: String) -> Result<String, rusqlite::Error> {
-    let stmt = connection.prepare("DELETE FROM events WHERE id=?1;")?;
-    let stmt2 = connection.prepare("DELETE FROM tickets WHERE event_id=?1;")?;
-    execute_delete_event(stmt, stmt2, params)
+pub async fn set_checkin_window(pool: &Pool, event_id: i64, settings: checkin::CheckinWindowSettings) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute(
+            "UPDATE events SET checkin_opens_before = ?1, checkin_closes_after = ?2 WHERE id = ?3 AND deleted_at IS NULL;",
+            params![settings.opens_before, settings.closes_after, event_id],
+        )?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
 }
 
-fn execute_delete_event(mut statement: Statement, mut statement_2: Statement, params: String) -> Result<String, rusqlite::Error> {
-    if statement.execute([params.clone()]).is_ok() {
-        if statement_2.execute([params]).is_ok() {
-            Ok("{\"status\":200}".to_string())
-        } else {
-            Err(rusqlite::Error::ExecuteReturnedResults)
-        }
-    } else {
-        Err(rusqlite::Error::ExecuteReturnedResults)
+// the event's check-in code key, created the first time an admin puts a code on screen
+pub async fn get_checkin_secret(pool: &Pool, event_id: i64) -> Result<String, Error> {
+    let pool = pool.clone();
+    let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), 40);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.execute("UPDATE events SET checkin_secret = ?1 WHERE id = ?2 AND checkin_secret = '';", params![secret, event_id])?;
+        conn.query_row("SELECT checkin_secret FROM events WHERE id = ?1;", params![event_id], |row| row.get::<_, String>(0))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// days a deleted event stays in the trash before the purge job removes it for good
+pub fn retention_days() -> i64 {
+    env::var("EVENT_RETENTION_DAYS").ok().and_then(|value| value.parse::<i64>().ok()).unwrap_or(30)
+}
+
+// moves an event to the trash. tickets stay so attendance history survives a restore
+pub async fn trash_event(pool: &Pool, event_id: i64, now: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute("UPDATE events SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL;", params![now, event_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn restore_event(pool: &Pool, event_id: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute("UPDATE events SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL;", params![event_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_trash(pool: &Pool) -> Result<Vec<Event>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let stmt = conn.prepare("SELECT * FROM events WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC;")?;
+        get_event_rows(stmt)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// permanently removes events trashed before cutoff, with everything that refers to them. returns how many were removed
+pub fn purge_trash_sql(conn: &mut rusqlite::Connection, cutoff: i64) -> Result<usize, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    delete_event_rows(&tx, "SELECT id FROM events WHERE deleted_at < ?1", cutoff)?;
+    let purged = tx.execute("DELETE FROM events WHERE deleted_at < ?1;", params![cutoff])?;
+    tx.commit()?;
+    Ok(purged)
+}
+
+// the rows hanging off the events the query selects: tickets, waitlists and surveys with their responses.
+// notifications are left, they're plain text and don't point at events
+fn delete_event_rows(conn: &rusqlite::Connection, event_ids: &str, parameter: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        format!("DELETE FROM survey_answers WHERE response_id IN (SELECT id FROM survey_responses WHERE event_id IN ({}));", event_ids).as_str(),
+        params![parameter],
+    )?;
+    for table in ["tickets", "waitlist", "surveys", "survey_questions", "survey_responses"] {
+        conn.execute(format!("DELETE FROM {} WHERE event_id IN ({});", table, event_ids).as_str(), params![parameter])?;
     }
+    Ok(())
+}
+
+pub async fn delete_event(pool: &Pool, params: String) -> Result<String, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        delete_event_sql(conn, params)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+fn delete_event_sql(mut connection: Connection, params: String) -> Result<String, rusqlite::Error> {
+    let event_id = params.parse::<i64>().map_err(|_| rusqlite::Error::ExecuteReturnedResults)?;
+    let tx = connection.transaction()?;
+    delete_event_rows(&tx, "?1", event_id)?;
+    tx.execute("DELETE FROM events WHERE id = ?1;", params![event_id])?;
+    tx.commit()?;
+    Ok("{\"status\":200}".to_string())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -216,11 +743,123 @@ pub struct EventCreateData {
     pub latitude: f64,
     pub longitude: f64,
     pub details: String,
-    pub image: String, // 640x320 or 1280x640
+    pub image: String, // url of an uploaded 1280x640 image
     pub point_reward: i64,
+    pub capacity: Option<i64>,
+    pub rsvp_cutoff: Option<i64>,
+    #[serde(default)]
+    pub rrule: String,
+    #[serde(default)]
+    pub exdates: Vec<i64>,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "default_status")]
+    pub status: String,
+    pub publish_at: Option<i64>,
+    #[serde(default = "checkin::default_mode")]
+    pub self_checkin: String,
+    pub checkin_radius: Option<i64>,
+    pub checkin_opens_before: Option<i64>,
+    pub checkin_closes_after: Option<i64>,
+    pub organization_id: Option<i64>,
+    #[serde(default)]
+    pub timezone: String, // empty for the deployment's zone
+}
+
+// fields left out of an edit keep their current value
+#[derive(Deserialize)]
+pub struct EventEditData {
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    pub title: Option<String>,
+    pub human_location: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub details: Option<String>,
+    pub image: Option<String>,
+    pub point_reward: Option<i64>,
+    // an explicit null clears the limit, leaving capacity out keeps it
+    #[serde(default, deserialize_with = "present")]
+    pub capacity: Option<Option<i64>>,
+    pub rsvp_cutoff: Option<i64>,
+    pub rrule: Option<String>,
+    pub category: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub timezone: Option<String>,
+}
+
+// tells a field sent as null apart from one left out, which serde reads as the same none
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub async fn edit_event(pool: &Pool, event_id: i64, data: EventEditData) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let (details_html, details_text) = match &data.details {
+            Some(details) => (Some(markdown::to_html(details)), Some(markdown::to_plain(details))),
+            None => (None, None),
+        };
+        let changed = conn.execute(
+            "UPDATE events SET
+                start_time = COALESCE(?1, start_time),
+                end_time = COALESCE(?2, end_time),
+                title = COALESCE(?3, title),
+                human_location = COALESCE(?4, human_location),
+                latitude = COALESCE(?5, latitude),
+                longitude = COALESCE(?6, longitude),
+                details = COALESCE(?7, details),
+                image = COALESCE(?8, image),
+                point_reward = COALESCE(?9, point_reward),
+                capacity = CASE WHEN ?19 THEN ?10 ELSE capacity END,
+                rsvp_cutoff = COALESCE(?11, rsvp_cutoff),
+                rrule = COALESCE(?12, rrule),
+                category = COALESCE(?13, category),
+                tags = COALESCE(?14, tags),
+                timezone = COALESCE(?15, timezone),
+                details_html = COALESCE(?16, details_html),
+                details_text = COALESCE(?17, details_text)
+            WHERE id = ?18 AND deleted_at IS NULL;",
+            params![
+                data.start_time,
+                data.end_time,
+                data.title,
+                data.human_location,
+                data.latitude,
+                data.longitude,
+                data.details,
+                data.image,
+                data.point_reward,
+                data.capacity.flatten(),
+                data.rsvp_cutoff,
+                data.rrule,
+                data.category.map(|category| category.to_lowercase()),
+                data.tags.map(|tags| normalize_tags(&tags).join(",")),
+                data.timezone,
+                details_html,
+                details_text,
+                event_id,
+                data.capacity.is_some()
+            ],
+        )?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
 }
 
 pub async fn execute_insert(pool: &Pool, data: web::Json<EventCreateData>) -> Result<String, actix_web::Error> {
+    insert_event(pool, data.into_inner()).await?;
+    Ok("done".to_string())
+}
+
+// inserts an event and returns its id
+pub async fn insert_event(pool: &Pool, data: EventCreateData) -> Result<i64, actix_web::Error> {
     // clone pools for all databases
     let pool = pool.clone();
 
@@ -232,8 +871,8 @@ pub async fn execute_insert(pool: &Pool, data: web::Json<EventCreateData>) -> Re
         .map_err(error::ErrorInternalServerError)
 }
 
-fn insert_main_data(conn: Connection, data: &web::Json<EventCreateData>) -> Result<String, rusqlite::Error> {
-    let mut stmt = conn.prepare("INSERT INTO events (start_time, end_time, title, human_location, latitude, longitude, details, image, point_reward) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);")?;
+fn insert_main_data(conn: Connection, data: &EventCreateData) -> Result<i64, rusqlite::Error> {
+    let mut stmt = conn.prepare("INSERT INTO events (start_time, end_time, title, human_location, latitude, longitude, details, image, point_reward, capacity, rsvp_cutoff, rrule, exdates, category, tags, status, publish_at, self_checkin, checkin_radius, checkin_opens_before, checkin_closes_after, organization_id, timezone, details_html, details_text) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);")?;
     stmt.execute(params![
         data.start_time,
         data.end_time,
@@ -243,8 +882,1113 @@ fn insert_main_data(conn: Connection, data: &web::Json<EventCreateData>) -> Resu
         data.longitude,
         data.details,
         data.image,
-        data.point_reward
+        data.point_reward,
+        data.capacity,
+        data.rsvp_cutoff,
+        data.rrule,
+        join_exdates(&data.exdates),
+        data.category.to_lowercase(),
+        normalize_tags(&data.tags).join(","),
+        data.status,
+        if data.status == "scheduled" { data.publish_at } else { None },
+        data.self_checkin,
+        data.checkin_radius,
+        data.checkin_opens_before,
+        data.checkin_closes_after,
+        data.organization_id,
+        data.timezone,
+        markdown::to_html(&data.details),
+        markdown::to_plain(&data.details)
     ])?;
 
-    Ok("done".to_string())
+    Ok(conn.last_insert_rowid())
+}
+
+// an event read from an uploaded calendar. imports never touch image, point_reward or capacity of events they update
+pub struct EventImportData {
+    pub uid: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub title: String,
+    pub human_location: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub details: String,
+    pub rrule: String,
+    pub exdates: Vec<i64>,
+    pub timezone: String, // the TZID of DTSTART, empty when it had none
+}
+
+#[derive(Serialize)]
+pub struct ImportChange {
+    pub uid: String,
+    pub title: String,
+    pub start_time: i64,
+    pub action: String, // create, update, unchanged, or trashed for events in the trash, which are left alone
+    pub event_id: Option<i64>,
+}
+
+// matches imported events to existing ones by UID. without commit the transaction is rolled back, so previews and imports share one path
+pub async fn import_events(pool: &Pool, events: Vec<EventImportData>, point_reward: i64, commit: bool) -> Result<Vec<ImportChange>, Error> {
+    let pool = pool.clone();
+
+    let mut conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || import_events_sql(&mut conn, events, point_reward, commit))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn import_events_sql(conn: &mut rusqlite::Connection, events: Vec<EventImportData>, point_reward: i64, commit: bool) -> Result<Vec<ImportChange>, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let mut changes = Vec::new();
+    for data in events {
+        let existing = tx
+            .query_row("SELECT * FROM events WHERE uid = ?1;", params![data.uid], event_from_row)
+            .optional()?;
+        let exdates = join_exdates(&data.exdates);
+        let (action, event_id) = match existing {
+            None => {
+                tx.execute(
+                    "INSERT INTO events (start_time, end_time, title, human_location, latitude, longitude, details, image, point_reward, rrule, exdates, uid, timezone, details_html, details_text) VALUES (?, ?, ?, ?, ?, ?, ?, '', ?, ?, ?, ?, ?, ?, ?);",
+                    params![data.start_time, data.end_time, data.title, data.human_location, data.latitude, data.longitude, data.details, point_reward, data.rrule, exdates, data.uid, data.timezone, markdown::to_html(&data.details), markdown::to_plain(&data.details)],
+                )?;
+                ("create", tx.last_insert_rowid())
+            }
+            // an event someone deleted stays deleted, rather than being quietly updated in the trash or brought back. it can be restored by hand
+            Some(event) if event.deleted_at.is_some() => ("trashed", event.id),
+            Some(event)
+                if event.start_time == data.start_time
+                    && event.end_time == data.end_time
+                    && event.title == data.title
+                    && event.human_location == data.human_location
+                    && event.latitude == data.latitude
+                    && event.longitude == data.longitude
+                    && event.details == data.details
+                    && event.rrule == data.rrule
+                    && event.exdates == data.exdates
+                    && event.timezone == timezone::zone(&data.timezone).name() =>
+            {
+                ("unchanged", event.id)
+            }
+            Some(event) => {
+                tx.execute(
+                    "UPDATE events SET start_time = ?1, end_time = ?2, title = ?3, human_location = ?4, latitude = ?5, longitude = ?6, details = ?7, rrule = ?8, exdates = ?9, timezone = ?10, details_html = ?11, details_text = ?12 WHERE id = ?13;",
+                    params![data.start_time, data.end_time, data.title, data.human_location, data.latitude, data.longitude, data.details, data.rrule, exdates, data.timezone, markdown::to_html(&data.details), markdown::to_plain(&data.details), event.id],
+                )?;
+                ("update", event.id)
+            }
+        };
+        changes.push(ImportChange {
+            uid: data.uid,
+            title: data.title,
+            start_time: data.start_time,
+            action: action.to_string(),
+            // ids of new events only exist once committed
+            event_id: if commit || action != "create" { Some(event_id) } else { None },
+        });
+    }
+    if commit {
+        tx.commit()?;
+    }
+    Ok(changes)
+}
+
+#[derive(Serialize, Clone)]
+pub struct WaitlistEntry {
+    pub id: i64,
+    pub event_id: i64,
+    pub user_id: i64,
+    pub position: i64,
+    pub creation_date: i64,
+    pub occurrence: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WaitlistOrderData {
+    pub user_ids: Vec<i64>,
+}
+
+pub enum WaitlistJoin {
+    Joined(i64), // place in line, starting at 1
+    SeatsAvailable,
+    AlreadyHeld,
+}
+
+fn get_waitlist_sql(conn: &rusqlite::Connection, event_id: i64, occurrence: i64) -> Result<Vec<WaitlistEntry>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM waitlist WHERE event_id = ?1 AND occurrence = ?2 ORDER BY position ASC, id ASC;")?;
+    let entries = stmt
+        .query_map(params![event_id, occurrence], |row| {
+            Ok(WaitlistEntry {
+                id: row.get(0)?,
+                event_id: row.get(1)?,
+                user_id: row.get(2)?,
+                position: row.get(3)?,
+                creation_date: row.get(4)?,
+                occurrence: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<WaitlistEntry>, rusqlite::Error>>()?;
+    Ok(entries)
+}
+
+pub async fn get_waitlist(pool: &Pool, event: &Event) -> Result<Vec<WaitlistEntry>, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence) = (event.id, event.occurrence);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || get_waitlist_sql(&conn, event_id, occurrence))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn join_waitlist(pool: &Pool, event: &Event, user_id: i64, now: i64) -> Result<WaitlistJoin, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence, capacity) = (event.id, event.occurrence, event.capacity);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || join_waitlist_sql(conn, event_id, occurrence, capacity, user_id, now))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn join_waitlist_sql(mut conn: Connection, event_id: i64, occurrence: i64, capacity: Option<i64>, user_id: i64, now: i64) -> Result<WaitlistJoin, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if held_ticket(&tx, event_id, occurrence, user_id)?.is_some() {
+        return Ok(WaitlistJoin::AlreadyHeld);
+    }
+    // only full events get a line
+    match capacity {
+        Some(capacity) if seats_taken(&tx, event_id, occurrence)? >= capacity => {}
+        _ => return Ok(WaitlistJoin::SeatsAvailable),
+    }
+    tx.execute(
+        "INSERT OR IGNORE INTO waitlist (event_id, user_id, position, creation_date, occurrence) VALUES (?1, ?2, (SELECT COALESCE(MAX(position), 0) + 1 FROM waitlist WHERE event_id = ?1 AND occurrence = ?4), ?3, ?4);",
+        params![event_id, user_id, now, occurrence],
+    )?;
+    let place = get_waitlist_sql(&tx, event_id, occurrence)?.iter().position(|entry| entry.user_id == user_id).unwrap_or(0) as i64 + 1;
+    tx.commit()?;
+    Ok(WaitlistJoin::Joined(place))
+}
+
+pub async fn leave_waitlist(pool: &Pool, event: &Event, user_id: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence) = (event.id, event.occurrence);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute("DELETE FROM waitlist WHERE event_id = ?1 AND occurrence = ?2 AND user_id = ?3;", params![event_id, occurrence, user_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// listed users move to the front in the given order, everyone else keeps their relative order behind them
+pub async fn reorder_waitlist(pool: &Pool, event: &Event, user_ids: Vec<i64>) -> Result<Vec<WaitlistEntry>, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence) = (event.id, event.occurrence);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || reorder_waitlist_sql(conn, event_id, occurrence, user_ids))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn reorder_waitlist_sql(mut conn: Connection, event_id: i64, occurrence: i64, user_ids: Vec<i64>) -> Result<Vec<WaitlistEntry>, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut entries = get_waitlist_sql(&tx, event_id, occurrence)?;
+    entries.sort_by_key(|entry| user_ids.iter().position(|user_id| *user_id == entry.user_id).unwrap_or(usize::MAX));
+    for (index, entry) in entries.iter().enumerate() {
+        tx.execute("UPDATE waitlist SET position = ?1 WHERE id = ?2;", params![index as i64 + 1, entry.id])?;
+    }
+    let entries = get_waitlist_sql(&tx, event_id, occurrence)?;
+    tx.commit()?;
+    Ok(entries)
+}
+
+// fills any open seats from the front of the waitlist, issuing reservations and notifying whoever got one
+pub async fn promote_waitlist(pool: &Pool, event: &Event, now: i64) -> Result<Vec<Ticket>, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence, capacity) = (event.id, event.occurrence, event.capacity);
+    let message = format!("A spot opened up for {} and you've been moved off the waitlist. Your ticket is reserved.", event.title);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || promote_waitlist_sql(conn, event_id, occurrence, capacity, now, message))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+// promotes every occurrence that has people waiting, for when an event's capacity goes up
+pub async fn promote_waitlists(pool: &Pool, event: &Event, now: i64) -> Result<Vec<Ticket>, Error> {
+    let pool_clone = pool.clone();
+    let event_id = event.id;
+
+    let conn = web::block(move || pool_clone.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    let occurrences = web::block(move || {
+        let mut stmt = conn.prepare("SELECT DISTINCT occurrence FROM waitlist WHERE event_id = ?1;")?;
+        let occurrences = stmt.query_map(params![event_id], |row| row.get::<_, i64>(0))?.collect::<Result<Vec<i64>, rusqlite::Error>>();
+        occurrences
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+
+    let mut promoted = Vec::new();
+    for occurrence in occurrences {
+        let event = if event.recurring() { event.at_occurrence(occurrence) } else { event.clone() };
+        promoted.extend(promote_waitlist(pool, &event, now).await?);
+    }
+    Ok(promoted)
+}
+
+fn promote_waitlist_sql(mut conn: Connection, event_id: i64, occurrence: i64, capacity: Option<i64>, now: i64, message: String) -> Result<Vec<Ticket>, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut promoted = Vec::new();
+    for entry in get_waitlist_sql(&tx, event_id, occurrence)? {
+        if let Some(capacity) = capacity {
+            if seats_taken(&tx, event_id, occurrence)? >= capacity {
+                break;
+            }
+        }
+        tx.execute("DELETE FROM waitlist WHERE id = ?1;", params![entry.id])?;
+        // someone checked in at the door while waiting doesn't need a second seat
+        if held_ticket(&tx, event_id, occurrence, entry.user_id)?.is_some() {
+            continue;
+        }
+        promoted.push(insert_ticket(&tx, event_id, occurrence, entry.user_id, now, "reserved")?);
+        notify_sql(&tx, entry.user_id, "You're off the waitlist", &message, now)?;
+    }
+    tx.commit()?;
+    Ok(promoted)
+}
+
+#[derive(Serialize, Clone)]
+pub struct Notification {
+    pub id: i64,
+    pub user_id: i64,
+    pub title: String,
+    pub body: String,
+    pub creation_date: i64,
+    pub read: bool,
+}
+
+fn notify_sql(conn: &rusqlite::Connection, user_id: i64, title: &str, body: &str, now: i64) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO notifications (user_id, title, body, creation_date, read) VALUES (?, ?, ?, ?, 0);",
+        params![user_id, title, body, now],
+    )
+}
+
+pub async fn get_notifications(pool: &Pool, user_id: i64) -> Result<Vec<Notification>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || get_notifications_sql(conn, user_id))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn get_notifications_sql(conn: Connection, user_id: i64) -> Result<Vec<Notification>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM notifications WHERE user_id = ?1 ORDER BY id DESC LIMIT 100;")?;
+    stmt.query_map(params![user_id], |row| {
+        Ok(Notification {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            title: row.get(2)?,
+            body: row.get(3)?,
+            creation_date: row.get(4)?,
+            read: row.get(5)?,
+        })
+    })
+    .and_then(Iterator::collect)
+}
+
+pub async fn mark_notifications_read(pool: &Pool, user_id: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.execute("UPDATE notifications SET read = 1 WHERE user_id = ?1;", params![user_id])?;
+        Ok::<bool, rusqlite::Error>(true)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// drops one occurrence of a recurring event. reservations and waitlist spots for it are released and their holders notified
+pub async fn cancel_occurrence(pool: &Pool, event: &Event, now: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence) = (event.id, event.occurrence);
+    // the date on the event's own clock, or evening events would show the next day
+    let date = timezone::to_local(&timezone::zone(&event.timezone), occurrence).map(|date| date.format("%b %-d").to_string()).unwrap_or_default();
+    let message = format!("{} on {} has been cancelled. Your reservation for it has been released.", event.title, date);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || cancel_occurrence_sql(conn, event_id, occurrence, now, message))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn cancel_occurrence_sql(mut conn: Connection, event_id: i64, occurrence: i64, now: i64, message: String) -> Result<bool, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let exdates: String = tx.query_row("SELECT exdates FROM events WHERE id = ?1;", params![event_id], |row| row.get(0))?;
+    let mut exdates = split_exdates(exdates);
+    if !exdates.contains(&occurrence) {
+        exdates.push(occurrence);
+    }
+    tx.execute("UPDATE events SET exdates = ?1 WHERE id = ?2;", params![join_exdates(&exdates), event_id])?;
+    let holders = {
+        let mut stmt = tx.prepare("SELECT holder_id FROM tickets WHERE event_id = ?1 AND occurrence = ?2 AND status = 'reserved' UNION SELECT user_id FROM waitlist WHERE event_id = ?1 AND occurrence = ?2;")?;
+        let holders = stmt.query_map(params![event_id, occurrence], |row| row.get::<_, i64>(0))?.collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        holders
+    };
+    for holder in holders {
+        notify_sql(&tx, holder, "Event cancelled", &message, now)?;
+    }
+    tx.execute("DELETE FROM tickets WHERE event_id = ?1 AND occurrence = ?2 AND status = 'reserved';", params![event_id, occurrence])?;
+    tx.execute("DELETE FROM waitlist WHERE event_id = ?1 AND occurrence = ?2;", params![event_id, occurrence])?;
+    tx.commit()?;
+    Ok(true)
+}
+
+#[derive(Deserialize)]
+pub struct EventCancelData {
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub reverse_points: bool,
+}
+
+// cancels an event but keeps its record. live tickets are voided and returned as they were before voiding, the waitlist is dropped and everyone affected is notified
+pub async fn cancel_event(pool: &Pool, event: &Event, reason: String, now: i64) -> Result<Vec<Ticket>, Error> {
+    let pool = pool.clone();
+    let event_id = event.id;
+    let mut message = format!("{} has been cancelled.", event.title);
+    if !reason.is_empty() {
+        message = format!("{} {}", message, reason);
+    }
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || cancel_event_sql(conn, event_id, now, message))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn cancel_event_sql(mut conn: Connection, event_id: i64, now: i64, message: String) -> Result<Vec<Ticket>, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute("UPDATE events SET status = 'cancelled', publish_at = NULL WHERE id = ?1;", params![event_id])?;
+    let voided = {
+        let mut stmt = tx.prepare("SELECT * FROM tickets WHERE event_id = ?1 AND status IN ('reserved', 'checked_in');")?;
+        let voided = stmt.query_map(params![event_id], ticket_from_row)?.collect::<Result<Vec<Ticket>, rusqlite::Error>>()?;
+        voided
+    };
+    let waitlisted = {
+        let mut stmt = tx.prepare("SELECT DISTINCT user_id FROM waitlist WHERE event_id = ?1;")?;
+        let waitlisted = stmt.query_map(params![event_id], |row| row.get::<_, i64>(0))?.collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        waitlisted
+    };
+    // passes already in wallet aren't updated by the server, so holders are asked to download theirs again to see it voided
+    let holder_message = format!("{} Your ticket is no longer valid. Download its pass again from the app to update it in Wallet.", message);
+    let mut notified: Vec<i64> = Vec::new();
+    for ticket in &voided {
+        if !notified.contains(&ticket.holder_id) {
+            notify_sql(&tx, ticket.holder_id, "Event cancelled", &holder_message, now)?;
+            notified.push(ticket.holder_id);
+        }
+    }
+    for user_id in waitlisted {
+        if !notified.contains(&user_id) {
+            notify_sql(&tx, user_id, "Event cancelled", &message, now)?;
+            notified.push(user_id);
+        }
+    }
+    tx.execute("UPDATE tickets SET status = 'void' WHERE event_id = ?1 AND status IN ('reserved', 'checked_in');", params![event_id])?;
+    tx.execute("DELETE FROM waitlist WHERE event_id = ?1;", params![event_id])?;
+    tx.commit()?;
+    Ok(voided)
+}
+
+#[derive(Serialize, Clone)]
+pub struct Organization {
+    pub id: i64,
+    pub name: String,
+    pub creation_date: i64,
+}
+
+#[derive(Deserialize)]
+pub struct OrganizationCreateData {
+    pub name: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Membership {
+    pub organization_id: i64,
+    pub user_id: i64,
+    pub role: String, // member or officer
+    pub creation_date: i64,
+}
+
+#[derive(Deserialize)]
+pub struct MembershipData {
+    pub student_id: String,
+    pub role: String,
+}
+
+pub const ROLES: [&str; 2] = ["member", "officer"];
+
+pub async fn create_organization(pool: &Pool, name: String, now: i64) -> Result<Organization, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.execute("INSERT INTO organizations (name, creation_date) VALUES (?1, ?2);", params![name, now])?;
+        Ok::<Organization, rusqlite::Error>(Organization { id: conn.last_insert_rowid(), name, creation_date: now })
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_organizations(pool: &Pool) -> Result<Vec<Organization>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("SELECT * FROM organizations ORDER BY name ASC;")?;
+        let organizations = stmt
+            .query_map([], |row| Ok(Organization { id: row.get(0)?, name: row.get(1)?, creation_date: row.get(2)? }))?
+            .collect::<Result<Vec<Organization>, rusqlite::Error>>()?;
+        Ok::<Vec<Organization>, rusqlite::Error>(organizations)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+fn membership_from_row(row: &Row) -> Result<Membership, rusqlite::Error> {
+    Ok(Membership {
+        organization_id: row.get(0)?,
+        user_id: row.get(1)?,
+        role: row.get(2)?,
+        creation_date: row.get(3)?,
+    })
+}
+
+// a user's role in an organization, none if they aren't in it
+pub async fn get_role(pool: &Pool, organization_id: i64, user_id: i64) -> Result<Option<String>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.query_row(
+            "SELECT role FROM organization_members WHERE organization_id = ?1 AND user_id = ?2;",
+            params![organization_id, user_id],
+            |row| row.get(0),
+        )
+        .optional()
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_members(pool: &Pool, organization_id: i64) -> Result<Vec<Membership>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("SELECT * FROM organization_members WHERE organization_id = ?1 ORDER BY role DESC, creation_date ASC;")?;
+        let members = stmt.query_map(params![organization_id], membership_from_row)?.collect::<Result<Vec<Membership>, rusqlite::Error>>()?;
+        Ok::<Vec<Membership>, rusqlite::Error>(members)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_memberships(pool: &Pool, user_id: i64) -> Result<Vec<Membership>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("SELECT * FROM organization_members WHERE user_id = ?1;")?;
+        let memberships = stmt.query_map(params![user_id], membership_from_row)?.collect::<Result<Vec<Membership>, rusqlite::Error>>()?;
+        Ok::<Vec<Membership>, rusqlite::Error>(memberships)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// adds a member or changes their role
+pub async fn set_member(pool: &Pool, organization_id: i64, user_id: i64, role: String, now: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        if conn.query_row("SELECT COUNT(*) FROM organizations WHERE id = ?1;", params![organization_id], |row| row.get::<_, i64>(0))? == 0 {
+            return Ok(false);
+        }
+        conn.execute(
+            "INSERT INTO organization_members (organization_id, user_id, role, creation_date) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (organization_id, user_id) DO UPDATE SET role = excluded.role;",
+            params![organization_id, user_id, role, now],
+        )?;
+        Ok::<bool, rusqlite::Error>(true)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn remove_member(pool: &Pool, organization_id: i64, user_id: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute("DELETE FROM organization_members WHERE organization_id = ?1 AND user_id = ?2;", params![organization_id, user_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// every event an organization runs, drafts included, for its officers
+pub async fn get_organization_events(pool: &Pool, organization_id: i64) -> Result<Vec<Event>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("SELECT * FROM events WHERE organization_id = ?1 AND deleted_at IS NULL ORDER BY start_time DESC;")?;
+        let events = stmt.query_map(params![organization_id], event_from_row)?.collect::<Result<Vec<Event>, rusqlite::Error>>()?;
+        Ok::<Vec<Event>, rusqlite::Error>(events)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// looks up an event whether or not it is in the trash
+pub async fn get_event_any(pool: &Pool, event_id: i64) -> Result<Option<Event>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || conn.query_row("SELECT * FROM events WHERE id = ?1;", params![event_id], event_from_row).optional())
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+// the parts of an event that stay the same from one to the next. anything left out has to be given when an event is made from the template
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct EventTemplateFields {
+    pub duration: Option<i64>, // minutes, used when no end_time is given
+    pub human_location: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub details: Option<String>,
+    pub image: Option<String>,
+    pub point_reward: Option<i64>,
+    pub capacity: Option<i64>,
+    pub category: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub self_checkin: Option<String>,
+    pub checkin_radius: Option<i64>,
+    pub checkin_opens_before: Option<i64>,
+    pub checkin_closes_after: Option<i64>,
+}
+
+impl EventTemplateFields {
+    // fields set here win over the template's
+    fn or(self, template: EventTemplateFields) -> EventTemplateFields {
+        EventTemplateFields {
+            duration: self.duration.or(template.duration),
+            human_location: self.human_location.or(template.human_location),
+            latitude: self.latitude.or(template.latitude),
+            longitude: self.longitude.or(template.longitude),
+            details: self.details.or(template.details),
+            image: self.image.or(template.image),
+            point_reward: self.point_reward.or(template.point_reward),
+            capacity: self.capacity.or(template.capacity),
+            category: self.category.or(template.category),
+            tags: self.tags.or(template.tags),
+            self_checkin: self.self_checkin.or(template.self_checkin),
+            checkin_radius: self.checkin_radius.or(template.checkin_radius),
+            checkin_opens_before: self.checkin_opens_before.or(template.checkin_opens_before),
+            checkin_closes_after: self.checkin_closes_after.or(template.checkin_closes_after),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct EventTemplate {
+    pub id: i64,
+    pub name: String,
+    pub organization_id: Option<i64>,
+    #[serde(flatten)]
+    pub fields: EventTemplateFields,
+    pub creation_date: i64,
+}
+
+#[derive(Deserialize)]
+pub struct EventTemplateCreateData {
+    pub name: String,
+    pub organization_id: Option<i64>,
+    #[serde(flatten)]
+    pub fields: EventTemplateFields,
+}
+
+// a new event from a template needs a title and a start, anything else given overrides the template
+#[derive(Deserialize)]
+pub struct EventFromTemplateData {
+    pub title: String,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    #[serde(default = "default_status")]
+    pub status: String,
+    pub publish_at: Option<i64>,
+    #[serde(flatten)]
+    pub fields: EventTemplateFields,
+}
+
+// events last an hour unless the template says otherwise
+const DEFAULT_DURATION_MINUTES: i64 = 60;
+
+impl EventTemplate {
+    // the names of required fields neither the template nor the request filled in come back as the error
+    pub fn event(&self, data: EventFromTemplateData) -> Result<EventCreateData, Vec<&'static str>> {
+        let fields = data.fields.or(self.fields.clone());
+        let mut missing = Vec::new();
+        if fields.human_location.is_none() {
+            missing.push("human_location");
+        }
+        if fields.latitude.is_none() || fields.longitude.is_none() {
+            missing.push("latitude");
+            missing.push("longitude");
+        }
+        if fields.point_reward.is_none() {
+            missing.push("point_reward");
+        }
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+        Ok(EventCreateData {
+            start_time: data.start_time,
+            end_time: data.end_time.unwrap_or(data.start_time + fields.duration.unwrap_or(DEFAULT_DURATION_MINUTES) * 60_000),
+            title: data.title,
+            human_location: fields.human_location.unwrap_or_default(),
+            latitude: fields.latitude.unwrap_or_default(),
+            longitude: fields.longitude.unwrap_or_default(),
+            details: fields.details.unwrap_or_default(),
+            image: fields.image.unwrap_or_default(),
+            point_reward: fields.point_reward.unwrap_or_default(),
+            capacity: fields.capacity,
+            rsvp_cutoff: None,
+            rrule: String::new(),
+            exdates: Vec::new(),
+            category: fields.category.unwrap_or_default(),
+            tags: fields.tags.unwrap_or_default(),
+            status: data.status,
+            publish_at: data.publish_at,
+            self_checkin: fields.self_checkin.unwrap_or_else(checkin::default_mode),
+            checkin_radius: fields.checkin_radius,
+            checkin_opens_before: fields.checkin_opens_before,
+            checkin_closes_after: fields.checkin_closes_after,
+            organization_id: self.organization_id,
+            timezone: String::new(),
+        })
+    }
+}
+
+fn template_from_row(row: &Row) -> Result<EventTemplate, rusqlite::Error> {
+    Ok(EventTemplate {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        organization_id: row.get(2)?,
+        fields: EventTemplateFields {
+            duration: row.get(3)?,
+            human_location: row.get(4)?,
+            latitude: row.get(5)?,
+            longitude: row.get(6)?,
+            details: row.get(7)?,
+            image: row.get(8)?,
+            point_reward: row.get(9)?,
+            capacity: row.get(10)?,
+            category: row.get(11)?,
+            tags: row.get::<_, Option<String>>(12)?.map(split_tags),
+            self_checkin: row.get(13)?,
+            checkin_radius: row.get(14)?,
+            checkin_opens_before: row.get(15)?,
+            checkin_closes_after: row.get(16)?,
+        },
+        creation_date: row.get(17)?,
+    })
+}
+
+pub async fn create_template(pool: &Pool, data: EventTemplateCreateData, now: i64) -> Result<i64, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let fields = data.fields;
+        conn.execute(
+            "INSERT INTO event_templates (name, organization_id, duration, human_location, latitude, longitude, details, image, point_reward, capacity, category, tags, self_checkin, checkin_radius, checkin_opens_before, checkin_closes_after, creation_date)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17);",
+            params![
+                data.name,
+                data.organization_id,
+                fields.duration,
+                fields.human_location,
+                fields.latitude,
+                fields.longitude,
+                fields.details,
+                fields.image,
+                fields.point_reward,
+                fields.capacity,
+                fields.category.map(|category| category.to_lowercase()),
+                fields.tags.map(|tags| normalize_tags(&tags).join(",")),
+                fields.self_checkin,
+                fields.checkin_radius,
+                fields.checkin_opens_before,
+                fields.checkin_closes_after,
+                now
+            ],
+        )?;
+        Ok::<i64, rusqlite::Error>(conn.last_insert_rowid())
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+// every template, or only those of the given organizations
+pub async fn get_templates(pool: &Pool, organization_ids: Option<Vec<i64>>) -> Result<Vec<EventTemplate>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let mut stmt = conn.prepare("SELECT * FROM event_templates ORDER BY name ASC;")?;
+        let templates = stmt
+            .query_map([], template_from_row)?
+            .filter(|template| match (&organization_ids, template) {
+                (Some(organization_ids), Ok(template)) => template.organization_id.is_some_and(|id| organization_ids.contains(&id)),
+                _ => true,
+            })
+            .collect::<Result<Vec<EventTemplate>, rusqlite::Error>>()?;
+        Ok::<Vec<EventTemplate>, rusqlite::Error>(templates)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn get_template(pool: &Pool, template_id: i64) -> Result<Option<EventTemplate>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || conn.query_row("SELECT * FROM event_templates WHERE id = ?1;", params![template_id], template_from_row).optional())
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn delete_template(pool: &Pool, template_id: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let changed = conn.execute("DELETE FROM event_templates WHERE id = ?1;", params![template_id])?;
+        Ok::<bool, rusqlite::Error>(changed == 1)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+#[derive(Deserialize)]
+pub struct EventDuplicateData {
+    pub start_time: i64,
+    pub title: Option<String>,
+    #[serde(default = "default_status")]
+    pub status: String,
+    pub publish_at: Option<i64>,
+}
+
+impl Event {
+    // a one-off copy at a new time. length and the rsvp cutoff keep their distance from the start, tickets and the import UID aren't copied
+    pub fn duplicate(&self, data: EventDuplicateData) -> EventCreateData {
+        let shift = data.start_time - self.start_time;
+        EventCreateData {
+            start_time: data.start_time,
+            end_time: self.end_time + shift,
+            title: data.title.unwrap_or_else(|| self.title.clone()),
+            human_location: self.human_location.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            details: self.details.clone(),
+            image: self.image.clone(),
+            point_reward: self.point_reward,
+            capacity: self.capacity,
+            rsvp_cutoff: self.rsvp_cutoff.map(|cutoff| cutoff + shift),
+            rrule: String::new(),
+            exdates: Vec::new(),
+            category: self.category.clone(),
+            tags: self.tags.clone(),
+            status: data.status,
+            publish_at: data.publish_at,
+            self_checkin: self.self_checkin.clone(),
+            checkin_radius: self.checkin_radius,
+            checkin_opens_before: self.checkin_opens_before,
+            checkin_closes_after: self.checkin_closes_after,
+            organization_id: self.organization_id,
+            timezone: self.timezone.clone(),
+        }
+    }
+}
+
+// replaces an event's survey. refused once anyone has answered, since their answers belong to the old questions
+pub async fn set_survey(pool: &Pool, event_id: i64, settings: survey::SurveySettings, now: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || set_survey_sql(conn, event_id, settings, now))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn set_survey_sql(mut conn: Connection, event_id: i64, settings: survey::SurveySettings, now: i64) -> Result<bool, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if tx.query_row("SELECT COUNT(*) FROM survey_responses WHERE event_id = ?1;", params![event_id], |row| row.get::<_, i64>(0))? > 0 {
+        return Ok(false);
+    }
+    tx.execute(
+        "INSERT INTO surveys (event_id, bonus_points, open_days, creation_date) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (event_id) DO UPDATE SET bonus_points = excluded.bonus_points, open_days = excluded.open_days;",
+        params![event_id, settings.bonus_points, settings.open_days, now],
+    )?;
+    tx.execute("DELETE FROM survey_questions WHERE event_id = ?1;", params![event_id])?;
+    let questions = if settings.questions.is_empty() { survey::default_questions() } else { settings.questions };
+    for (position, question) in questions.iter().enumerate() {
+        let options: Vec<&str> = question.options.iter().map(|option| option.trim()).filter(|option| !option.is_empty()).collect();
+        tx.execute(
+            "INSERT INTO survey_questions (event_id, position, kind, prompt, options, required) VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            params![event_id, position as i64, question.kind, question.prompt.trim(), options.join("\n"), question.required],
+        )?;
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+pub async fn get_survey(pool: &Pool, event_id: i64) -> Result<Option<survey::Survey>, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let settings = conn
+            .query_row("SELECT bonus_points, open_days FROM surveys WHERE event_id = ?1;", params![event_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))
+            .optional()?;
+        let (bonus_points, open_days) = match settings {
+            Some(settings) => settings,
+            None => return Ok(None),
+        };
+        let mut stmt = conn.prepare("SELECT id, kind, prompt, options, required FROM survey_questions WHERE event_id = ?1 ORDER BY position ASC;")?;
+        let questions = stmt
+            .query_map(params![event_id], |row| {
+                Ok(survey::Question {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    prompt: row.get(2)?,
+                    options: row.get::<_, String>(3)?.split('\n').filter(|option| !option.is_empty()).map(str::to_string).collect(),
+                    required: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<survey::Question>, rusqlite::Error>>()?;
+        Ok::<Option<survey::Survey>, rusqlite::Error>(Some(survey::Survey { event_id, bonus_points, open_days, questions }))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+pub async fn has_responded(pool: &Pool, event_id: i64, occurrence: i64, user_id: i64) -> Result<bool, Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        conn.query_row(
+            "SELECT COUNT(*) FROM survey_responses WHERE event_id = ?1 AND occurrence = ?2 AND user_id = ?3;",
+            params![event_id, occurrence, user_id],
+            |row| row.get::<_, i64>(0),
+        )
+    })
+    .await?
+    .map(|count| count > 0)
+    .map_err(error::ErrorInternalServerError)
+}
+
+// stores one attendee's answers. none if they already answered for this occurrence
+pub async fn submit_survey_response(pool: &Pool, event: &Event, user_id: i64, answers: Vec<(i64, String)>, now: i64) -> Result<Option<i64>, Error> {
+    let pool = pool.clone();
+    let (event_id, occurrence) = (event.id, event.occurrence);
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || submit_survey_response_sql(conn, event_id, occurrence, user_id, answers, now))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+fn submit_survey_response_sql(mut conn: Connection, event_id: i64, occurrence: i64, user_id: i64, answers: Vec<(i64, String)>, now: i64) -> Result<Option<i64>, rusqlite::Error> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let inserted = tx.execute(
+        "INSERT OR IGNORE INTO survey_responses (event_id, occurrence, user_id, creation_date) VALUES (?1, ?2, ?3, ?4);",
+        params![event_id, occurrence, user_id, now],
+    )?;
+    if inserted == 0 {
+        return Ok(None);
+    }
+    let response_id = tx.last_insert_rowid();
+    for (question_id, value) in answers {
+        tx.execute("INSERT INTO survey_answers (response_id, question_id, value) VALUES (?1, ?2, ?3);", params![response_id, question_id, value])?;
+    }
+    tx.commit()?;
+    Ok(Some(response_id))
+}
+
+// the number of responses and every answer given, for one occurrence or all of them
+pub async fn get_survey_answers(pool: &Pool, event_id: i64, occurrence: Option<i64>) -> Result<(usize, Vec<(i64, String)>), Error> {
+    let pool = pool.clone();
+
+    let conn = web::block(move || pool.get()).await?.map_err(error::ErrorInternalServerError)?;
+
+    web::block(move || {
+        let responses = conn.query_row(
+            "SELECT COUNT(*) FROM survey_responses WHERE event_id = ?1 AND (?2 IS NULL OR occurrence = ?2);",
+            params![event_id, occurrence],
+            |row| row.get::<_, i64>(0),
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT survey_answers.question_id, survey_answers.value FROM survey_answers
+            JOIN survey_responses ON survey_responses.id = survey_answers.response_id
+            WHERE survey_responses.event_id = ?1 AND (?2 IS NULL OR survey_responses.occurrence = ?2)
+            ORDER BY survey_responses.creation_date ASC;",
+        )?;
+        let answers = stmt
+            .query_map(params![event_id, occurrence], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<(i64, String)>, rusqlite::Error>>()?;
+        Ok::<(usize, Vec<(i64, String)>), rusqlite::Error>((responses as usize, answers))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the tables as they were before migrations, which bring them up to date
+    fn database() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE events (id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, start_time INTEGER NOT NULL, end_time INTEGER NOT NULL, title TEXT NOT NULL, human_location TEXT NOT NULL, latitude REAL NOT NULL DEFAULT 0.0, longitude REAL NOT NULL DEFAULT 0.0, details TEXT NOT NULL, image TEXT NOT NULL, point_reward INTEGER NOT NULL DEFAULT 0);
+            CREATE TABLE tickets (id INTEGER NOT NULL PRIMARY KEY, event_id INTEGER NOT NULL, holder_id INTEGER NOT NULL, creation_date INTEGER NOT NULL);",
+        )
+        .unwrap();
+        crate::db_schema::migrate_main(&conn).unwrap();
+        conn
+    }
+
+    fn imported(title: &str) -> EventImportData {
+        EventImportData {
+            uid: "club-meeting@example.com".to_string(),
+            start_time: 1_730_000_000_000,
+            end_time: 1_730_003_600_000,
+            title: title.to_string(),
+            human_location: "Library".to_string(),
+            latitude: 0.0,
+            longitude: 0.0,
+            details: String::new(),
+            rrule: String::new(),
+            exdates: Vec::new(),
+            timezone: String::new(),
+        }
+    }
+
+    #[test]
+    fn imports_leave_trashed_events_alone() {
+        let mut conn = database();
+        let created = import_events_sql(&mut conn, vec![imported("Club meeting")], 5, true).unwrap();
+        assert_eq!(created[0].action, "create");
+        let event_id = created[0].event_id.unwrap();
+        conn.execute("UPDATE events SET deleted_at = 1 WHERE id = ?1;", params![event_id]).unwrap();
+
+        let changes = import_events_sql(&mut conn, vec![imported("Club meeting, moved")], 5, true).unwrap();
+        assert_eq!(changes[0].action, "trashed");
+        assert_eq!(changes[0].event_id, Some(event_id));
+        let (title, deleted_at): (String, Option<i64>) =
+            conn.query_row("SELECT title, deleted_at FROM events WHERE id = ?1;", params![event_id], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(title, "Club meeting");
+        assert_eq!(deleted_at, Some(1));
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM events;", [], |row| row.get::<_, i64>(0)).unwrap(), 1);
+    }
+
+    #[test]
+    fn purging_removes_what_refers_to_the_event() {
+        let mut conn = database();
+        let kept = import_events_sql(&mut conn, vec![imported("Kept")], 5, true).unwrap()[0].event_id.unwrap();
+        let mut other = imported("Purged");
+        other.uid = "purged@example.com".to_string();
+        let purged = import_events_sql(&mut conn, vec![other], 5, true).unwrap()[0].event_id.unwrap();
+        for event_id in [kept, purged] {
+            conn.execute_batch(&format!(
+                "INSERT INTO tickets (event_id, holder_id, creation_date) VALUES ({0}, 1, 0);
+                INSERT INTO waitlist (event_id, user_id, position, creation_date) VALUES ({0}, 2, 1, 0);
+                INSERT INTO surveys (event_id, creation_date) VALUES ({0}, 0);
+                INSERT INTO survey_questions (event_id, position, kind, prompt) VALUES ({0}, 0, 'rating', 'How was it?');
+                INSERT INTO survey_responses (event_id, user_id, creation_date) VALUES ({0}, 1, 0);
+                INSERT INTO survey_answers (response_id, question_id, value) VALUES (last_insert_rowid(), 1, '5');",
+                event_id
+            ))
+            .unwrap();
+        }
+        conn.execute("UPDATE events SET deleted_at = 1 WHERE id = ?1;", params![purged]).unwrap();
+
+        assert_eq!(purge_trash_sql(&mut conn, 2).unwrap(), 1);
+        for table in ["events", "tickets", "waitlist", "surveys", "survey_questions", "survey_responses", "survey_answers"] {
+            let count = conn.query_row(format!("SELECT COUNT(*) FROM {};", table).as_str(), [], |row| row.get::<_, i64>(0)).unwrap();
+            assert_eq!(count, 1, "{}", table);
+        }
+        let column = |table: &str| conn.query_row(format!("SELECT event_id FROM {};", table).as_str(), [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(column("tickets"), kept);
+        assert_eq!(column("survey_responses"), kept);
+    }
+
+    #[test]
+    fn edits_tell_a_cleared_capacity_from_a_missing_one() {
+        let edit = |body: &str| serde_json::from_str::<EventEditData>(body).unwrap().capacity;
+        assert_eq!(edit("{}"), None);
+        assert_eq!(edit("{\"capacity\": null}"), Some(None));
+        assert_eq!(edit("{\"capacity\": 40}"), Some(Some(40)));
+    }
+
+    #[test]
+    fn imports_update_live_events() {
+        let mut conn = database();
+        import_events_sql(&mut conn, vec![imported("Club meeting")], 5, true).unwrap();
+        let changes = import_events_sql(&mut conn, vec![imported("Club meeting, moved")], 5, true).unwrap();
+        assert_eq!(changes[0].action, "update");
+    }
 }
@@ -0,0 +1,193 @@
+use rusqlite::{params, Connection};
+
+use crate::markdown;
+
+// brings databases copied from older releases up to the current schema. runs on every start, so every step must be idempotent
+pub fn migrate_auth(conn: &Connection) -> Result<(), rusqlite::Error> {
+    add_column(conn, "users", "cohort", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "users", "suspended", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(conn, "users", "suspension_reason", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "users", "hidden", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(conn, "users", "calendar_token", "TEXT NOT NULL DEFAULT ''")?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS invites (
+            code TEXT NOT NULL PRIMARY KEY,
+            cohort TEXT NOT NULL DEFAULT '',
+            max_uses INTEGER NOT NULL DEFAULT 1,
+            uses INTEGER NOT NULL DEFAULT 0,
+            expires INTEGER NOT NULL DEFAULT 0,
+            created_by INTEGER NOT NULL,
+            creation_date INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS name_reviews (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            field TEXT NOT NULL,
+            value TEXT NOT NULL,
+            matched TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            creation_date INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS point_ledger (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            amount INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            reference TEXT NOT NULL,
+            creation_date INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            actor_id INTEGER NOT NULL,
+            subject_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            detail TEXT NOT NULL,
+            creation_date INTEGER NOT NULL
+        );",
+    )
+}
+
+pub fn migrate_main(conn: &Connection) -> Result<(), rusqlite::Error> {
+    // null capacity means unlimited, null cutoff means reservations can be cancelled until the event starts
+    add_column(conn, "events", "capacity", "INTEGER")?;
+    add_column(conn, "events", "rsvp_cutoff", "INTEGER")?;
+    // tickets issued at the door before reservations existed are all checked in
+    add_column(conn, "tickets", "status", "TEXT NOT NULL DEFAULT 'checked_in'")?;
+    add_column(conn, "tickets", "checkin_date", "INTEGER NOT NULL DEFAULT 0")?;
+    // recurring events. tickets and waitlist spots belong to one occurrence, identified by its start time
+    add_column(conn, "events", "rrule", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "events", "exdates", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "tickets", "occurrence", "INTEGER NOT NULL DEFAULT 0")?;
+    // events imported from calendar files are matched to their source by UID
+    add_column(conn, "events", "uid", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "events", "category", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "events", "tags", "TEXT NOT NULL DEFAULT ''")?;
+    // events from before publish states were visible right away
+    add_column(conn, "events", "status", "TEXT NOT NULL DEFAULT 'published'")?;
+    add_column(conn, "events", "publish_at", "INTEGER")?;
+    add_column(conn, "events", "deleted_at", "INTEGER")?;
+    add_column(conn, "events", "self_checkin", "TEXT NOT NULL DEFAULT 'off'")?;
+    add_column(conn, "events", "checkin_radius", "INTEGER")?;
+    add_column(conn, "events", "checkin_secret", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "events", "checkin_opens_before", "INTEGER")?;
+    add_column(conn, "events", "checkin_closes_after", "INTEGER")?;
+    add_column(conn, "events", "organization_id", "INTEGER")?;
+    // empty follows EVENT_TIMEZONE
+    add_column(conn, "events", "timezone", "TEXT NOT NULL DEFAULT ''")?;
+    // details rendered from markdown when the event is written, rather than on every read
+    add_column(conn, "events", "details_html", "TEXT NOT NULL DEFAULT ''")?;
+    add_column(conn, "events", "details_text", "TEXT NOT NULL DEFAULT ''")?;
+    render_details(conn)?;
+    conn.execute_batch(
+        "UPDATE tickets SET checkin_date = creation_date WHERE status = 'checked_in' AND checkin_date = 0;
+        CREATE UNIQUE INDEX IF NOT EXISTS events_uid ON events (uid) WHERE uid != '';
+        CREATE TABLE IF NOT EXISTS waitlist (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            event_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            creation_date INTEGER NOT NULL,
+            occurrence INTEGER NOT NULL DEFAULT 0,
+            UNIQUE(event_id, occurrence, user_id)
+        );
+        CREATE TABLE IF NOT EXISTS organizations (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            creation_date INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS organization_members (
+            organization_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            role TEXT NOT NULL DEFAULT 'member',
+            creation_date INTEGER NOT NULL,
+            PRIMARY KEY (organization_id, user_id)
+        );
+        CREATE TABLE IF NOT EXISTS event_templates (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            organization_id INTEGER,
+            duration INTEGER,
+            human_location TEXT,
+            latitude REAL,
+            longitude REAL,
+            details TEXT,
+            image TEXT,
+            point_reward INTEGER,
+            capacity INTEGER,
+            category TEXT,
+            tags TEXT,
+            self_checkin TEXT,
+            checkin_radius INTEGER,
+            checkin_opens_before INTEGER,
+            checkin_closes_after INTEGER,
+            creation_date INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS surveys (
+            event_id INTEGER NOT NULL PRIMARY KEY,
+            bonus_points INTEGER NOT NULL DEFAULT 0,
+            open_days INTEGER NOT NULL DEFAULT 7,
+            creation_date INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS survey_questions (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            event_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            prompt TEXT NOT NULL,
+            options TEXT NOT NULL DEFAULT '',
+            required INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS survey_responses (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            event_id INTEGER NOT NULL,
+            occurrence INTEGER NOT NULL DEFAULT 0,
+            user_id INTEGER NOT NULL,
+            creation_date INTEGER NOT NULL,
+            UNIQUE(event_id, occurrence, user_id)
+        );
+        CREATE TABLE IF NOT EXISTS survey_answers (
+            response_id INTEGER NOT NULL,
+            question_id INTEGER NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (response_id, question_id)
+        );
+        CREATE TABLE IF NOT EXISTS notifications (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            body TEXT NOT NULL,
+            creation_date INTEGER NOT NULL,
+            read INTEGER NOT NULL DEFAULT 0
+        );",
+    )?;
+    add_column(conn, "waitlist", "occurrence", "INTEGER NOT NULL DEFAULT 0")
+}
+
+// events written before details were rendered on save. details that render to nothing are redone each start, which is cheap
+fn render_details(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT id, details FROM events WHERE details != '' AND details_text = '';")?;
+    let unrendered = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<(i64, String)>, rusqlite::Error>>()?;
+    for (id, details) in unrendered {
+        conn.execute(
+            "UPDATE events SET details_html = ?1, details_text = ?2 WHERE id = ?3;",
+            params![markdown::to_html(&details), markdown::to_plain(&details), id],
+        )?;
+    }
+    Ok(())
+}
+
+// sqlite has no ADD COLUMN IF NOT EXISTS, so check table_info first
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(format!("PRAGMA table_info({});", table).as_str())?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition).as_str())?;
+    }
+    Ok(())
+}
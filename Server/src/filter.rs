@@ -0,0 +1,179 @@
+use once_cell::sync::Lazy;
+use std::{env, fs};
+
+// term lists are plain text, one term per line, # for comments. matched against normalized names
+static BLOCKLIST: Lazy<Vec<String>> = Lazy::new(|| load_terms("NAME_BLOCKLIST", "./filter/blocklist.txt"));
+static REVIEWLIST: Lazy<Vec<String>> = Lazy::new(|| load_terms("NAME_REVIEWLIST", "./filter/reviewlist.txt"));
+
+pub enum Verdict {
+    Allowed,
+    Review(String),
+    Blocked(String),
+}
+
+fn load_terms(key: &str, default_path: &str) -> Vec<String> {
+    let path = env::var(key).unwrap_or_else(|_| default_path.to_string());
+    match fs::read_to_string(&path) {
+        Ok(contents) => contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(normalize)
+            .filter(|term| !term.is_empty())
+            .collect(),
+        Err(_) => {
+            log::warn!("name filter: could not read {}, list is empty", path);
+            Vec::new()
+        }
+    }
+}
+
+// latin lookalikes from cyrillic and greek
+fn homoglyph(c: char) -> char {
+    match c {
+        'а' | 'α' => 'a',
+        'в' | 'β' => 'b',
+        'с' | 'ϲ' => 'c',
+        'е' | 'ε' => 'e',
+        'н' | 'η' => 'h',
+        'і' | 'ι' | 'ї' => 'i',
+        'ј' => 'j',
+        'к' | 'κ' => 'k',
+        'м' | 'μ' => 'm',
+        'п' => 'n',
+        'о' | 'ο' | 'σ' => 'o',
+        'р' | 'ρ' => 'p',
+        'ѕ' => 's',
+        'т' | 'τ' => 't',
+        'υ' | 'у' => 'y',
+        'х' | 'χ' => 'x',
+        'ν' => 'v',
+        'ω' | 'ш' => 'w',
+        'ᴢ' | 'ζ' => 'z',
+        // fullwidth ascii
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn leetspeak(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' | '|' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '6' | '9' => 'g',
+        '7' | '+' => 't',
+        '8' => 'b',
+        _ => c,
+    }
+}
+
+// folds lookalikes and leetspeak to plain letters, drops spacing and punctuation, and collapses repeats ("F u u-c k" -> "fuck")
+pub fn normalize(value: &str) -> String {
+    let mut normalized = String::with_capacity(value.len());
+    for c in value.chars().flat_map(char::to_lowercase).map(homoglyph).map(leetspeak) {
+        if c.is_ascii_lowercase() && !normalized.ends_with(c) {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+// usernames are matched as substrings, so run-together words are caught
+pub fn check_name(value: &str) -> Verdict {
+    verdict(&[normalize(value)], &BLOCKLIST, &REVIEWLIST, |name, term| name.contains(term))
+}
+
+// real names are matched a word at a time. as substrings, Matsushita and Scunthorpe would be blocked and Dickson sent to review
+pub fn check_full_name(value: &str) -> Verdict {
+    verdict(&words(value), &BLOCKLIST, &REVIEWLIST, |word, term| word == term)
+}
+
+fn words(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '\'')
+        .map(normalize)
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+fn verdict(names: &[String], blocklist: &[String], reviewlist: &[String], matches: impl Fn(&str, &str) -> bool) -> Verdict {
+    let find = |list: &[String]| list.iter().find(|term| names.iter().any(|name| matches(name, term))).cloned();
+    if let Some(term) = find(blocklist) {
+        return Verdict::Blocked(term);
+    }
+    if let Some(term) = find(reviewlist) {
+        return Verdict::Review(term);
+    }
+    Verdict::Allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> (Vec<String>, Vec<String>) {
+        let terms = |terms: &[&str]| terms.iter().map(|term| normalize(term)).collect::<Vec<String>>();
+        (terms(&["fuck", "shit", "cunt", "faggot"]), terms(&["cock", "dick", "sex"]))
+    }
+
+    fn username(value: &str) -> Verdict {
+        let (blocklist, reviewlist) = lists();
+        verdict(&[normalize(value)], &blocklist, &reviewlist, |name, term| name.contains(term))
+    }
+
+    fn full_name(value: &str) -> Verdict {
+        let (blocklist, reviewlist) = lists();
+        verdict(&words(value), &blocklist, &reviewlist, |word, term| word == term)
+    }
+
+    #[test]
+    fn normalize_folds_spacing_and_repeats() {
+        assert_eq!(normalize("F u u-c k"), "fuck");
+        assert_eq!(normalize("SHIIIT"), "shit");
+        assert_eq!(normalize("faggot"), "fagot");
+    }
+
+    #[test]
+    fn normalize_folds_leetspeak() {
+        assert_eq!(normalize("$h1t"), "shit");
+        assert_eq!(normalize("fvck3r"), "fvcker");
+        assert_eq!(normalize("5h!7"), "shit");
+        assert_eq!(normalize("@55"), "as");
+    }
+
+    #[test]
+    fn normalize_folds_homoglyphs() {
+        // cyrillic ѕ and і
+        assert_eq!(normalize("\u{0455}h\u{0456}t"), "shit");
+        // greek alpha and cyrillic о
+        assert_eq!(normalize("b\u{03B1}st\u{043E}n"), "baston");
+        // fullwidth
+        assert_eq!(normalize("\u{FF46}\u{FF55}\u{FF43}\u{FF4B}"), "fuck");
+    }
+
+    #[test]
+    fn usernames_match_substrings() {
+        assert!(matches!(username("xX_$h1t_Xx"), Verdict::Blocked(term) if term == "shit"));
+        assert!(matches!(username("bigdickenergy"), Verdict::Review(term) if term == "dick"));
+        assert!(matches!(username("f.u.c.k"), Verdict::Blocked(_)));
+        assert!(matches!(username("student42"), Verdict::Allowed));
+    }
+
+    #[test]
+    fn real_names_are_allowed() {
+        for name in ["Yoshitaka Matsushita", "Scunthorpe", "Hancock", "Dickson", "Essex", "Sexton", "Mary-Ann O'Cockburn"] {
+            assert!(matches!(full_name(name), Verdict::Allowed), "{}", name);
+        }
+    }
+
+    #[test]
+    fn full_names_match_whole_words() {
+        assert!(matches!(full_name("Shit Head"), Verdict::Blocked(term) if term == "shit"));
+        assert!(matches!(full_name("Big $h1t"), Verdict::Blocked(_)));
+        assert!(matches!(full_name("Jane \u{0455}ex"), Verdict::Review(term) if term == "sex"));
+        assert!(matches!(full_name("Mary Dick-Smith"), Verdict::Review(term) if term == "dick"));
+    }
+}
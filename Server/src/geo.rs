@@ -0,0 +1,10 @@
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+// great-circle distance in meters between two lat/long points
+pub fn distance_m(latitude_a: f64, longitude_a: f64, latitude_b: f64, longitude_b: f64) -> f64 {
+    let (phi_a, phi_b) = (latitude_a.to_radians(), latitude_b.to_radians());
+    let delta_phi = (latitude_b - latitude_a).to_radians();
+    let delta_lambda = (longitude_b - longitude_a).to_radians();
+    let a = (delta_phi / 2.0).sin().powi(2) + phi_a.cos() * phi_b.cos() * (delta_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
@@ -0,0 +1,346 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::{collections::BTreeSet, env};
+
+use crate::db_main::{Event, EventImportData};
+use crate::{recurrence, timezone};
+
+// zone definitions cover at most this many years of a feed, each scanned day by day
+const MAX_ZONE_YEARS: usize = 20;
+
+// RFC 5545 calendars for subscribing from calendar apps. times are written in each event's zone so apps keep them on the local clock
+pub fn calendar(name: &str, events: &[Event]) -> String {
+    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let stamp = timestamp(Utc::now().timestamp_millis());
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//M-A Central Services//macsvc//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name)),
+        format!("X-WR-TIMEZONE:{}", timezone::default_timezone().name()),
+        "REFRESH-INTERVAL;VALUE=DURATION:PT1H".to_string(),
+    ];
+    // every zone in use needs a definition covering the years of the times written in it
+    let mut zones: Vec<(Tz, BTreeSet<i32>)> = Vec::new();
+    for event in events {
+        let zone = timezone::zone(&event.timezone);
+        let index = match zones.iter().position(|(used, _)| *used == zone) {
+            Some(index) => index,
+            None => {
+                zones.push((zone, BTreeSet::new()));
+                zones.len() - 1
+            }
+        };
+        for time in [event.start_time, event.end_time] {
+            if let Some(time) = Utc.timestamp_millis_opt(time).single() {
+                zones[index].1.insert(time.year());
+            }
+        }
+    }
+    for (zone, years) in &zones {
+        lines.extend(vtimezone(zone, years));
+    }
+    for event in events {
+        lines.push("BEGIN:VEVENT".to_string());
+        // each occurrence of a recurring event is its own entry
+        lines.push(format!("UID:event-{}-{}@{}", event.id, event.occurrence, hostname));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        let zone = timezone::zone(&event.timezone);
+        lines.push(format!("DTSTART;TZID={}:{}", zone.name(), local_timestamp(&zone, event.start_time)));
+        lines.push(format!("DTEND;TZID={}:{}", zone.name(), local_timestamp(&zone, event.end_time)));
+        lines.push(format!("SUMMARY:{}", escape(&event.title)));
+        lines.push(format!("LOCATION:{}", escape(&event.human_location)));
+        lines.push(format!("GEO:{};{}", event.latitude, event.longitude));
+        lines.push(format!("DESCRIPTION:{}", escape(&event.details_text)));
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect::<Vec<String>>().join("")
+}
+
+fn timestamp(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(|time| time.format("%Y%m%dT%H%M%SZ").to_string())
+        .unwrap_or_default()
+}
+
+fn local_timestamp(zone: &Tz, millis: i64) -> String {
+    timezone::to_local(zone, millis).map(|time| time.format("%Y%m%dT%H%M%S").to_string()).unwrap_or_default()
+}
+
+// one STANDARD or DAYLIGHT onset per offset change in the years, rather than rules, so it's exact for whatever the zone did in them
+fn vtimezone(zone: &Tz, years: &BTreeSet<i32>) -> Vec<String> {
+    let mut lines = vec!["BEGIN:VTIMEZONE".to_string(), format!("TZID:{}", zone.name())];
+    let mut found: Vec<timezone::Transition> = Vec::new();
+    for year in years.iter().take(MAX_ZONE_YEARS) {
+        let start = |year: i32| NaiveDate::from_ymd_opt(year, 1, 1).and_then(|date| date.and_hms_opt(0, 0, 0)).map(|time| time.and_utc().timestamp_millis());
+        let (from, to) = match (start(*year), start(*year + 1)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue,
+        };
+        for transition in timezone::transitions(zone, from, to) {
+            // the offset in effect at the start of a year only needs an onset if it isn't where the last change left it
+            if found.last().is_some_and(|last| transition.at == from && transition.from == transition.to && last.to == transition.to) {
+                continue;
+            }
+            found.push(transition);
+        }
+    }
+    for transition in found {
+        let component = if transition.daylight { "DAYLIGHT" } else { "STANDARD" };
+        // onsets are written in the local time in effect before them
+        let onset = Utc
+            .timestamp_millis_opt(transition.at + transition.from as i64 * 1000)
+            .single()
+            .map(|time| time.format("%Y%m%dT%H%M%S").to_string())
+            .unwrap_or_default();
+        lines.push(format!("BEGIN:{}", component));
+        lines.push(format!("DTSTART:{}", onset));
+        lines.push(format!("TZOFFSETFROM:{}", utc_offset(transition.from)));
+        lines.push(format!("TZOFFSETTO:{}", utc_offset(transition.to)));
+        if !transition.abbreviation.is_empty() {
+            lines.push(format!("TZNAME:{}", escape(&transition.abbreviation)));
+        }
+        lines.push(format!("END:{}", component));
+    }
+    lines.push("END:VTIMEZONE".to_string());
+    lines
+}
+
+fn utc_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    if seconds % 60 == 0 {
+        format!("{}{:02}{:02}", sign, seconds / 3600, seconds / 60 % 60)
+    } else {
+        format!("{}{:02}{:02}{:02}", sign, seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// content lines are limited to 75 octets, continued on the next line after a space
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[derive(Serialize)]
+pub struct SkippedEvent {
+    pub uid: String,
+    pub reason: String,
+}
+
+// reads the VEVENTs of an uploaded calendar. events that can't be represented are reported back rather than failing the whole file
+pub fn parse(body: &str) -> (Vec<EventImportData>, Vec<SkippedEvent>) {
+    let mut imported = Vec::new();
+    let mut skipped = Vec::new();
+    let mut properties: Option<Vec<Property>> = None;
+    for line in unfold(body) {
+        if line.eq_ignore_ascii_case("BEGIN:VEVENT") {
+            properties = Some(Vec::new());
+        } else if line.eq_ignore_ascii_case("END:VEVENT") {
+            if let Some(properties) = properties.take() {
+                match event_from_properties(&properties) {
+                    Ok(event) => imported.push(event),
+                    Err(reason) => skipped.push(SkippedEvent { uid: value_of(&properties, "UID").unwrap_or_default(), reason }),
+                }
+            }
+        } else if let Some(properties) = properties.as_mut() {
+            // properties of nested components like VALARM are ignored
+            if let Some(property) = Property::parse(&line) {
+                properties.push(property);
+            }
+        }
+    }
+    (imported, skipped)
+}
+
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        // the value starts at the first colon outside a quoted parameter
+        let mut quoted = false;
+        let split = line.char_indices().find(|(_, c)| {
+            if *c == '"' {
+                quoted = !quoted;
+            }
+            *c == ':' && !quoted
+        })?;
+        let (head, value) = (&line[..split.0], &line[split.0 + 1..]);
+        let mut parts = head.split(';');
+        let name = parts.next()?.to_ascii_uppercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_ascii_uppercase(), value.trim_matches('"').to_string()))
+            .collect();
+        Some(Property { name, params, value: value.to_string() })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+}
+
+fn unfold(body: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in body.split('\n').map(|line| line.trim_end_matches('\r')) {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ if !line.is_empty() => lines.push(line.to_string()),
+            _ => {}
+        }
+    }
+    lines
+}
+
+fn value_of(properties: &[Property], name: &str) -> Option<String> {
+    properties.iter().find(|property| property.name == name).map(|property| unescape(&property.value))
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => {}
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+fn event_from_properties(properties: &[Property]) -> Result<EventImportData, String> {
+    let uid = value_of(properties, "UID").filter(|uid| !uid.is_empty()).ok_or("missing UID")?;
+    if value_of(properties, "RECURRENCE-ID").is_some() {
+        return Err("changes to single occurrences are not supported".to_string());
+    }
+    if value_of(properties, "STATUS").is_some_and(|status| status.eq_ignore_ascii_case("CANCELLED")) {
+        return Err("event is cancelled".to_string());
+    }
+    let start = properties.iter().find(|property| property.name == "DTSTART").ok_or("missing DTSTART")?;
+    let (start_time, all_day) = parse_time(&start.value, start.param("TZID"))?;
+    let end_time = match properties.iter().find(|property| property.name == "DTEND") {
+        Some(end) => parse_time(&end.value, end.param("TZID"))?.0,
+        None => match value_of(properties, "DURATION") {
+            Some(duration) => start_time.checked_add(parse_duration(&duration)?).ok_or("bad DURATION")?,
+            // RFC 5545: all-day events without an end last the day, timed ones end when they start
+            None if all_day => start_time + 86_400_000,
+            None => start_time,
+        },
+    };
+    if !timezone::time_in_range(start_time) || !timezone::time_in_range(end_time) || end_time < start_time {
+        return Err("times out of range".to_string());
+    }
+    let rrule = value_of(properties, "RRULE").unwrap_or_default();
+    if !rrule.is_empty() {
+        recurrence::parse(&rrule)?;
+    }
+    let mut exdates = Vec::new();
+    for exdate in properties.iter().filter(|property| property.name == "EXDATE") {
+        for value in exdate.value.split(',') {
+            exdates.push(parse_time(value, exdate.param("TZID"))?.0);
+        }
+    }
+    let (latitude, longitude) = value_of(properties, "GEO")
+        .and_then(|geo| {
+            let (latitude, longitude) = geo.split_once(';')?;
+            Some((latitude.trim().parse::<f64>().ok()?, longitude.trim().parse::<f64>().ok()?))
+        })
+        .unwrap_or((0.0, 0.0));
+    Ok(EventImportData {
+        uid,
+        start_time,
+        end_time,
+        title: value_of(properties, "SUMMARY").unwrap_or_default(),
+        human_location: value_of(properties, "LOCATION").unwrap_or_default(),
+        latitude,
+        longitude,
+        details: value_of(properties, "DESCRIPTION").unwrap_or_default(),
+        rrule,
+        exdates,
+        timezone: start.param("TZID").map(|tzid| tzid.trim_start_matches('/')).filter(|tzid| timezone::valid(tzid)).unwrap_or_default().to_string(),
+    })
+}
+
+// epoch millis, and whether the value was a plain date
+fn parse_time(value: &str, tzid: Option<&str>) -> Result<(i64, bool), String> {
+    let value = value.trim();
+    let zone = match tzid {
+        Some(tzid) => tzid.trim_start_matches('/').parse::<Tz>().map_err(|_| format!("unknown TZID {}", tzid))?,
+        None => timezone::default_timezone(),
+    };
+    let (local, all_day) = match NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S") {
+        Ok(time) if value.ends_with('Z') => return Ok((time.and_utc().timestamp_millis(), false)),
+        Ok(time) => (time, false),
+        Err(_) => match NaiveDate::parse_from_str(value, "%Y%m%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)) {
+            Some(time) => (time, true),
+            None => return Err(format!("bad time {}", value)),
+        },
+    };
+    timezone::from_local(&zone, local).map(|millis| (millis, all_day)).ok_or_else(|| format!("bad time {}", value))
+}
+
+fn parse_duration(value: &str) -> Result<i64, String> {
+    let (negative, rest) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let rest = rest.strip_prefix('P').ok_or_else(|| format!("bad DURATION {}", value))?;
+    let mut millis: i64 = 0;
+    let mut number = String::new();
+    for c in rest.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'T' => continue,
+            'W' => 7 * 86_400_000,
+            'D' => 86_400_000,
+            'H' => 3_600_000,
+            'M' => 60_000,
+            'S' => 1_000,
+            _ => return Err(format!("bad DURATION {}", value)),
+        };
+        millis = number
+            .parse::<i64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .and_then(|part| millis.checked_add(part))
+            .ok_or_else(|| format!("bad DURATION {}", value))?;
+        number.clear();
+    }
+    Ok(if negative { -millis } else { millis })
+}
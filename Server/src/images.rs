@@ -0,0 +1,130 @@
+use actix_multipart::Multipart;
+use actix_web::{error, web, Error, HttpResponse};
+use futures_util::TryStreamExt;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat, ImageReader, Limits};
+use openssl::hash::MessageDigest;
+use serde::Serialize;
+use std::{env, fs, io::Cursor, path::{Path, PathBuf}};
+
+// stored variants: the two canonical event image sizes and a thumbnail for lists
+const VARIANTS: [(&str, u32, u32); 3] = [("1280", 1280, 640), ("640", 640, 320), ("thumb", 320, 160)];
+
+// largest width or height decoded. an upload past this is rejected rather than held in memory to be scaled down
+const MAX_DIMENSION: u32 = 8192;
+
+fn image_dir() -> PathBuf {
+    PathBuf::from(env::var("IMAGE_DIR").unwrap_or_else(|_| "./images".to_string()))
+}
+
+fn max_image_bytes() -> usize {
+    env::var("MAX_IMAGE_BYTES").ok().and_then(|value| value.parse::<usize>().ok()).unwrap_or(8 * 1024 * 1024)
+}
+
+fn image_url(name: &str) -> String {
+    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    format!("https://{}/api/v1/images/{}", hostname, name)
+}
+
+#[derive(Serialize)]
+pub struct StoredImage {
+    pub hash: String,
+    pub image: String, // 1280x640, what Event.image should be set to
+    pub medium: String,
+    pub thumbnail: String,
+}
+
+// reads the "image" field of a multipart upload, refusing anything over the size limit
+pub async fn read_upload(mut payload: Multipart) -> Result<Vec<u8>, Error> {
+    let limit = max_image_bytes();
+    while let Some(mut field) = payload.try_next().await? {
+        if field.name() != Some("image") {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if bytes.len() + chunk.len() > limit {
+                return Err(error::ErrorPayloadTooLarge("{\"status\": \"image_too_large\"}"));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+    Err(error::ErrorBadRequest("{\"status\": \"missing_image\"}"))
+}
+
+// decodes, crops and resizes an upload to every variant. files are named by the hash of the upload, so storing the same image twice is a no-op
+pub async fn store(bytes: Vec<u8>) -> Result<StoredImage, Error> {
+    match web::block(move || store_blocking(&bytes)).await? {
+        Ok(stored) => Ok(stored),
+        Err(StoreError::Format) => Err(error::ErrorUnsupportedMediaType("{\"status\": \"bad_image_format\"}")),
+        Err(StoreError::Unreadable) => Err(error::ErrorBadRequest("{\"status\": \"bad_image\"}")),
+        Err(StoreError::TooSmall) => Err(error::ErrorBadRequest("{\"status\": \"image_too_small\"}")),
+        Err(StoreError::Internal(err)) => Err(error::ErrorInternalServerError(err)),
+    }
+}
+
+// actix errors can't leave the blocking pool, so failures come back as this
+enum StoreError {
+    Format,
+    Unreadable,
+    TooSmall,
+    Internal(String),
+}
+
+fn store_blocking(bytes: &[u8]) -> Result<StoredImage, StoreError> {
+    let format = image::guess_format(bytes).map_err(|_| StoreError::Format)?;
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP) {
+        return Err(StoreError::Format);
+    }
+    // the header's size is checked before decoding, so a small file claiming huge dimensions can't take the server's memory
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let decoded = reader.decode().map_err(|_| StoreError::Unreadable)?;
+    if decoded.width() < 640 || decoded.height() < 320 {
+        return Err(StoreError::TooSmall);
+    }
+    let digest = openssl::hash::hash(MessageDigest::sha256(), bytes).map_err(|err| StoreError::Internal(err.to_string()))?;
+    let hash = digest.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join("");
+    let dir = image_dir();
+    fs::create_dir_all(&dir).map_err(|err| StoreError::Internal(err.to_string()))?;
+    for (suffix, width, height) in VARIANTS {
+        let path = dir.join(format!("{}-{}.jpg", hash, suffix));
+        if !path.exists() {
+            write_variant(&decoded, width, height, &path)?;
+        }
+    }
+    Ok(StoredImage {
+        image: image_url(&format!("{}-1280.jpg", hash)),
+        medium: image_url(&format!("{}-640.jpg", hash)),
+        thumbnail: image_url(&format!("{}-thumb.jpg", hash)),
+        hash,
+    })
+}
+
+fn write_variant(decoded: &DynamicImage, width: u32, height: u32, path: &Path) -> Result<(), StoreError> {
+    // jpeg has no alpha, so flatten first
+    let resized = decoded.resize_to_fill(width, height, FilterType::Lanczos3).to_rgb8();
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, 85).encode_image(&resized).map_err(|err| StoreError::Internal(err.to_string()))?;
+    // write then rename so a half written file is never served
+    let partial = path.with_extension("part");
+    fs::write(&partial, encoded).map_err(|err| StoreError::Internal(err.to_string()))?;
+    fs::rename(&partial, path).map_err(|err| StoreError::Internal(err.to_string()))
+}
+
+// stored images never change under the same name, so clients can keep them forever
+pub fn serve(name: &str) -> Result<HttpResponse, Error> {
+    let (hash, suffix) = name.strip_suffix(".jpg").and_then(|stem| stem.split_once('-')).ok_or_else(|| error::ErrorNotFound("{\"status\": \"not_found\"}"))?;
+    let valid = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) && VARIANTS.iter().any(|(variant, _, _)| *variant == suffix);
+    if !valid {
+        return Err(error::ErrorNotFound("{\"status\": \"not_found\"}"));
+    }
+    let bytes = fs::read(image_dir().join(name)).map_err(|_| error::ErrorNotFound("{\"status\": \"not_found\"}"))?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+        .content_type("image/jpeg")
+        .body(bytes))
+}
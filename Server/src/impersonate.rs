@@ -0,0 +1,159 @@
+use actix_identity::{Identity, RequestIdentity};
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse},
+    error,
+    http::{
+        header::{HeaderName, HeaderValue},
+        Method,
+    },
+    web, Error, HttpResponse,
+};
+use chrono::Utc;
+use futures_util::future::LocalBoxFuture;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{env, sync::RwLock};
+
+use crate::{db_auth, Databases, Sessions};
+
+const END_PATH: &str = "/api/v1/manage/impersonate/end";
+
+// an admin viewing the app as a student. keyed in Sessions by the identity the admin's cookie is switched to
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Impersonation {
+    pub admin_id: i64,
+    pub admin_username: String,
+    pub user_id: i64,
+    pub username: String,
+    pub expires: i64,
+}
+
+fn impersonation_minutes() -> i64 {
+    env::var("IMPERSONATION_MINUTES").ok().and_then(|value| value.parse::<i64>().ok()).unwrap_or(15)
+}
+
+pub async fn start(
+    db: &web::Data<Databases>,
+    session: web::Data<RwLock<Sessions>>,
+    identity: Identity,
+    admin: db_auth::User,
+    target_id: String,
+) -> Result<HttpResponse, Error> {
+    let target = db_auth::get_user_id(&db.auth, target_id).await.map_err(|_| error::ErrorBadRequest("{\"status\": \"bad_user_id\"}"))?;
+    // admin accounts can't be borrowed
+    if target.data == "admin" || target.id == admin.id {
+        return Err(error::ErrorForbidden("{\"status\": \"cannot_impersonate\"}"));
+    }
+    let impersonation = Impersonation {
+        admin_id: admin.id,
+        admin_username: admin.username.clone(),
+        user_id: target.id,
+        username: target.username.clone(),
+        expires: Utc::now().timestamp_millis() + impersonation_minutes() * 60_000,
+    };
+    db_auth::write_audit(&db.auth, admin.id, target.id, "impersonation_start", format!("until {}", impersonation.expires)).await?;
+    // the admin's own session stays in the map so ending can switch back to it
+    let key = format!("impersonate:{}", Alphanumeric.sample_string(&mut rand::thread_rng(), 48));
+    {
+        let mut sessions = session.write().unwrap();
+        sessions.user_map.insert(key.clone(), target);
+        sessions.impersonations.insert(key.clone(), impersonation.clone());
+    }
+    identity.remember(key);
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(json!({ "status": "impersonating", "user_id": impersonation.user_id, "username": impersonation.username, "expires": impersonation.expires })))
+}
+
+pub async fn end(db: &web::Data<Databases>, session: web::Data<RwLock<Sessions>>, identity: Identity) -> Result<HttpResponse, Error> {
+    let key = identity.identity().unwrap_or_default();
+    let impersonation = remove(&session, &key).ok_or_else(|| error::ErrorBadRequest("{\"status\": \"not_impersonating\"}"))?;
+    db_auth::write_audit(&db.auth, impersonation.admin_id, impersonation.user_id, "impersonation_end", "".to_string()).await?;
+    // hand the cookie back to the admin if their session is still around
+    if session.read().unwrap().user_map.contains_key(&impersonation.admin_username) {
+        identity.remember(impersonation.admin_username);
+    } else {
+        identity.forget();
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .body("{\"status\": \"success\"}"))
+}
+
+// removes an impersonation and the borrowed user session. used by end, logout and expiry
+pub fn remove(session: &web::Data<RwLock<Sessions>>, key: &str) -> Option<Impersonation> {
+    let mut sessions = session.write().unwrap();
+    let impersonation = sessions.impersonations.remove(key)?;
+    sessions.user_map.remove(key);
+    Some(impersonation)
+}
+
+// GETs that would hand over a credential outliving the impersonation, like the private calendar feed url
+const DENIED_PATHS: [&str; 2] = ["/api/v1/tickets_create", "/api/v1/user/calendar"];
+
+// impersonated sessions are read only. plain GET requests go through, marked with a response header and written to the audit log
+fn allowed(req: &ServiceRequest) -> bool {
+    req.path() == END_PATH
+        || req.path() == "/api/v1/auth/logout"
+        || (req.method() == Method::GET && !DENIED_PATHS.iter().any(|path| req.path().starts_with(path)))
+}
+
+pub fn guard<S, B>(req: ServiceRequest, srv: &S) -> LocalBoxFuture<'static, Result<ServiceResponse<EitherBody<B>>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let session = req.app_data::<web::Data<RwLock<Sessions>>>().cloned();
+    let db = req.app_data::<web::Data<Databases>>().cloned();
+    let key = req.get_identity();
+    let impersonation = match (&session, &key) {
+        (Some(session), Some(key)) => session.read().unwrap().impersonations.get(key).cloned(),
+        _ => None,
+    };
+    let (impersonation, session, db, key) = match (impersonation, session, db, key) {
+        (Some(impersonation), Some(session), Some(db), Some(key)) => (impersonation, session, db, key),
+        _ => {
+            let fut = srv.call(req);
+            return Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) });
+        }
+    };
+
+    if impersonation.expires < Utc::now().timestamp_millis() {
+        remove(&session, &key);
+        return Box::pin(async move {
+            db_auth::write_audit(&db.auth, impersonation.admin_id, impersonation.user_id, "impersonation_expired", "".to_string()).await?;
+            Ok(req.into_response(
+                HttpResponse::Unauthorized()
+                    .insert_header(("Cache-Control", "no-cache"))
+                    .body("{\"status\": \"impersonation_expired\"}")
+                    .map_into_right_body(),
+            ))
+        });
+    }
+
+    let detail = format!("{} {}", req.method(), req.path());
+    if !allowed(&req) {
+        return Box::pin(async move {
+            db_auth::write_audit(&db.auth, impersonation.admin_id, impersonation.user_id, "impersonation_blocked", detail).await?;
+            Ok(req.into_response(
+                HttpResponse::Forbidden()
+                    .insert_header(("Cache-Control", "no-cache"))
+                    .body("{\"status\": \"impersonation_read_only\"}")
+                    .map_into_right_body(),
+            ))
+        });
+    }
+
+    let fut = srv.call(req);
+    Box::pin(async move {
+        db_auth::write_audit(&db.auth, impersonation.admin_id, impersonation.user_id, "impersonation_request", detail).await?;
+        let mut res = fut.await?;
+        if let Ok(value) = HeaderValue::from_str(&format!("{} as {}", impersonation.admin_username, impersonation.username)) {
+            res.headers_mut().insert(HeaderName::from_static("x-macsvc-impersonation"), value);
+        }
+        Ok(res.map_into_left_body())
+    })
+}
@@ -25,16 +25,30 @@ use serde_json::json;
 use std::{collections::HashMap, env, fs, io, pin::Pin, sync::RwLock, time::{SystemTime, UNIX_EPOCH}, path::PathBuf};
 use tempfile::tempdir;
 
+mod attendance;
 mod auth;
+mod checkin;
 mod db_main;
 mod db_auth;
+mod db_schema;
+mod filter;
+mod geo;
+mod ical;
+mod images;
+mod impersonate;
+mod markdown;
+mod paging;
 mod pass;
+mod recurrence;
 mod session;
+mod survey;
+mod timezone;
 
 // hashmap containing user session IDs
 #[derive(Serialize, Deserialize, Default, Clone)]
 struct Sessions {
     user_map: HashMap<String, db_auth::User>,
+    impersonations: HashMap<String, impersonate::Impersonation>,
 }
 
 // gets a user object from requests. needed for db_auth::User param in handlers
@@ -69,6 +83,66 @@ fn get_secret_key() -> Key {
     Key::generate()
 }
 
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time just went fucking backwards")
+        .as_millis() as i64
+}
+
+fn event_id_param(req: &HttpRequest) -> Result<i64, AWError> {
+    req.match_info()
+        .get("event_id")
+        .and_then(|event_id| event_id.parse::<i64>().ok())
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+}
+
+async fn find_event(db: &web::Data<Databases>, event_id: i64) -> Result<db_main::Event, AWError> {
+    db_main::execute_events(&db.main, db_main::EventQuery::GetEventById, event_id as u128)
+        .await?
+        .pop()
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+}
+
+// admins manage every event, officers only their organization's
+async fn can_manage(db: &web::Data<Databases>, user: &db_auth::User, organization_id: Option<i64>) -> Result<bool, AWError> {
+    if user.data == "admin" {
+        return Ok(true);
+    }
+    match organization_id {
+        Some(organization_id) => Ok(db_main::get_role(&db.main, organization_id, user.id).await?.as_deref() == Some("officer")),
+        None => Ok(false),
+    }
+}
+
+// the event in the path, if the user may manage it
+async fn managed_event(req: &HttpRequest, db: &web::Data<Databases>, user: &db_auth::User) -> Result<db_main::Event, AWError> {
+    let event = find_event(db, event_id_param(req)?).await?;
+    if can_manage(db, user, event.organization_id).await? {
+        Ok(event)
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct OccurrenceQuery {
+    occurrence: Option<i64>,
+}
+
+// the event a request is about, moved to the occurrence in ?occurrence= (or the current or next one) when it recurs
+async fn find_occurrence(req: &HttpRequest, db: &web::Data<Databases>) -> Result<db_main::Event, AWError> {
+    let query = web::Query::<OccurrenceQuery>::from_query(req.query_string()).map_err(|_| error::ErrorBadRequest("{\"status\": \"bad_occurrence\"}"))?;
+    let event = find_event(db, event_id_param(req)?).await?;
+    // drafts and unpublished events don't exist as far as students can tell
+    if !event.published(now_millis()) {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"));
+    }
+    event
+        .resolve_occurrence(query.occurrence, now_millis())
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_occurrence\"}"))
+}
+
 async fn auth_post_create(db: web::Data<Databases>, data: web::Json<auth::CreateForm>) -> impl Responder {
     auth::create_account(&db.auth, data).await
 }
@@ -111,218 +185,1220 @@ async fn auth_get_admin(user: db_auth::User) -> Result<HttpResponse, AWError> {
     }
 }
 
+// the public board is a fixed top list. paging through everyone, and the count, is for signed in users
 async fn board_get_lifetime_top(db: web::Data<Databases>) -> Result<HttpResponse, AWError> {
+    let top = paging::PageQuery { limit: Some(db_auth::LEADERBOARD_TOP), cursor: None, sort: None };
+    let page = top.page(&db_auth::SCORE_SORTS, "-lifetime")?;
     Ok(HttpResponse::Ok()
         .insert_header(("Cache-Control", "max-age=60"))
-        .json(db_auth::execute_scores(&db.auth, db_auth::AuthData::GetUserScores, 0).await?)
+        .json(db_auth::get_user_scores(&db.auth, page).await?.items)
     )
 }
 
-async fn board_get_lifetime_all(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+async fn board_get_lifetime_all(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, _user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let page = query.page(&db_auth::SCORE_SORTS, "-lifetime")?;
+    Ok(paging::respond(db_auth::get_user_scores(&db.auth, page).await?, "max-age=60"))
+}
+
+async fn events_get_all(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let page = query.page(&db_main::EVENT_SORTS, "-start_time")?;
+        Ok(paging::respond(db_main::get_all_events(&db.main, page).await?, "no-cache"))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn events_get_future(filter: web::Query<db_main::EventFilter>, query: web::Query<paging::PageQuery>, db: web::Data<Databases>) -> Result<HttpResponse, AWError> {
+    let page = query.page(&db_main::EVENT_SORTS, "start_time")?;
+    let start = SystemTime::now();
+    let since_the_epoch = start
+        .duration_since(UNIX_EPOCH)
+        .expect("time just went fucking backwards");
+
+    let mut events = db_main::execute_events(&db.main, db_main::EventQuery::GetFutureEvents, since_the_epoch.as_millis()).await?;
+    events.retain(|event| filter.matches(event));
+    // occurrences of a recurring event share its id, so the tiebreak includes the start
+    let key = |event: &db_main::Event, sort: &str| {
+        (db_main::event_sort_key(event, sort), paging::Key::Text(format!("{:020}:{:020}", event.id, event.start_time)))
+    };
+    Ok(paging::respond(page.apply(events, key), "max-age=150"))
+}
+
+async fn events_get_calendar(db: web::Data<Databases>) -> Result<HttpResponse, AWError> {
+    let events = db_main::execute_events(&db.main, db_main::EventQuery::GetFutureEvents, now_millis() as u128).await?;
     Ok(HttpResponse::Ok()
-        .insert_header(("Cache-Control", "max-age=60"))
-        .json(db_auth::execute_scores(&db.auth, db_auth::AuthData::GetUserScores, user.id).await?)
-    )
+        .insert_header(("Cache-Control", "max-age=900"))
+        .content_type("text/calendar; charset=utf-8")
+        .body(ical::calendar("M-A Events", &events)))
+}
+
+// private feed of the events a user holds tickets for. the token in the url is the only credential, calendar apps can't log in
+async fn calendar_get_private(req: HttpRequest, db: web::Data<Databases>) -> Result<HttpResponse, AWError> {
+    let token = req.match_info().get("token").unwrap_or_default().to_string();
+    let user_id = db_auth::get_calendar_owner(&db.auth, token)
+        .await?
+        .ok_or_else(|| error::ErrorNotFound("{\"status\": \"bad_calendar_token\"}"))?;
+    let events = db_main::get_held_events(&db.main, user_id).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .content_type("text/calendar; charset=utf-8")
+        .body(ical::calendar("My M-A Events", &events)))
+}
+
+// null until the user asks for a feed
+fn calendar_url(token: Option<&str>) -> serde_json::Value {
+    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    json!({ "url": token.map(|token| format!("https://{}/api/v1/calendar/{}.ics", hostname, token)) })
+}
+
+async fn user_get_calendar(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let token = db_auth::get_calendar_token(&db.auth, user.id).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(calendar_url(token.as_deref())))
+}
+
+async fn user_post_calendar(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let token = db_auth::create_calendar_token(&db.auth, user.id, false).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(calendar_url(Some(&token))))
+}
+
+async fn user_post_calendar_reset(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let token = db_auth::create_calendar_token(&db.auth, user.id, true).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(calendar_url(Some(&token))))
+}
+
+async fn tickets_get_all(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let page = query.page(&db_main::TICKET_SORTS, "-creation_date")?;
+        Ok(paging::respond(db_main::get_all_tickets(&db.main, page).await?, "no-cache"))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckinQuery {
+    #[serde(rename = "override")]
+    override_window: Option<bool>,
+}
+
+// scans outside the check-in window are refused unless an admin passes ?override=true, which is audited. officers can only scan inside it
+async fn tickets_create_ticket(req: HttpRequest, query: web::Query<CheckinQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if !can_manage(&db, &user, event.organization_id).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    let student_id = req.match_info().get("user_id").unwrap();
+    let target_user = db_auth::get_user_student_id(&db.auth, student_id.to_string()).await?;
+    // officers run events for points like everyone else, so they can't award them to themselves
+    if user.data != "admin" && target_user.id == user.id {
+        return Err(error::ErrorForbidden("{\"status\": \"cannot_check_in_self\"}"));
+    }
+    if let Some(status) = checkin::window_status(&event, now_millis()) {
+        if query.override_window != Some(true) || user.data != "admin" {
+            let (opens, closes) = checkin::window(&event);
+            return Err(error::ErrorLocked(json!({ "status": status, "opens": opens, "closes": closes }).to_string()));
+        }
+        db_auth::write_audit(&db.auth, user.id, target_user.id, "checkin_override", format!("event {} {}", event.id, status)).await?;
+    }
+    admit_checkin(&db, &event, target_user.id).await
+}
+
+// checks a user in and awards the event's points. shared by admin scanning and self check-in
+async fn admit_checkin(db: &web::Data<Databases>, event: &db_main::Event, user_id: i64) -> Result<HttpResponse, AWError> {
+    match db_main::check_in(&db.main, event, user_id, now_millis()).await? {
+        db_main::Admission::Issued(ticket) => {
+            let point_deduction = db_auth::update_points(&db.auth, user_id, event.point_reward, "checkin", ticket.id.to_string()).await?;
+            if point_deduction {
+                Ok(HttpResponse::Ok()
+                    .insert_header(("Cache-Control", "no-cache"))
+                    .json(ticket))
+            } else {
+                Err(error::ErrorInternalServerError("{\"status\": \"point_transaction_failed\"}"))
+            }
+        }
+        db_main::Admission::AlreadyHeld => Err(error::ErrorLocked("{\"status\": \"ticket_sale_ended\"}")),
+        db_main::Admission::Full => Err(error::ErrorLocked("{\"status\": \"event_full\"}")),
+    }
+}
+
+// students checking themselves in from their phone's location, or with the code shown at the event
+async fn events_post_checkin(req: HttpRequest, data: web::Json<checkin::SelfCheckinData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if !checkin::self_checkin_allowed(user.data == "admin", can_manage(&db, &user, event.organization_id).await?) {
+        return Err(error::ErrorForbidden("{\"status\": \"cannot_check_in_self\"}"));
+    }
+    if let Err(status) = checkin::verify(&event, user.id, &data, now_millis()) {
+        return Err(error::ErrorForbidden(json!({ "status": status }).to_string()));
+    }
+    admit_checkin(&db, &event, user.id).await
+}
+
+// the rotating code for organizers to project. the admin screen polls this as codes expire
+async fn manage_get_checkin_code(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    let secret = db_main::get_checkin_secret(&db.main, event.id).await?;
+    let now = now_millis();
+    let code = checkin::current_code(&secret, now).ok_or_else(|| error::ErrorInternalServerError("{\"status\": \"code_failed\"}"))?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({ "code": code, "mode": event.self_checkin, "expires": (now.div_euclid(checkin::CODE_PERIOD_MS) + 1) * checkin::CODE_PERIOD_MS })))
+}
+
+async fn manage_set_checkin_window(req: HttpRequest, data: web::Json<checkin::CheckinWindowSettings>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    if !checkin::window_valid(data.opens_before, data.closes_after) {
+        return Err(error::ErrorBadRequest(checkin::window_error().to_string()));
+    }
+    if user.data != "admin" && !checkin::officer_window_allowed(data.opens_before, data.closes_after) {
+        return Err(error::ErrorBadRequest(checkin::officer_window_error().to_string()));
+    }
+    if db_main::set_checkin_window(&db.main, event.id, data.into_inner()).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+    }
+}
+
+async fn manage_set_self_checkin(req: HttpRequest, data: web::Json<checkin::SelfCheckinSettings>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    if !checkin::MODES.contains(&data.mode.as_str()) {
+        return Err(error::ErrorBadRequest(json!({ "status": "bad_mode", "modes": checkin::MODES }).to_string()));
+    }
+    if db_main::set_self_checkin(&db.main, event.id, data.into_inner()).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+    }
+}
+
+// reserve a seat ahead of time. points are only awarded at check-in
+async fn events_post_rsvp(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if now_millis() > event.rsvp_cutoff() {
+        return Err(error::ErrorLocked("{\"status\": \"rsvp_closed\"}"));
+    }
+    match db_main::reserve_ticket(&db.main, &event, user.id, now_millis()).await? {
+        db_main::Admission::Issued(ticket) => Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(ticket)),
+        db_main::Admission::AlreadyHeld => Err(error::ErrorConflict("{\"status\": \"already_has_ticket\"}")),
+        db_main::Admission::Full => Err(error::ErrorLocked("{\"status\": \"event_full\"}")),
+    }
+}
+
+async fn events_delete_rsvp(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if now_millis() > event.rsvp_cutoff() {
+        return Err(error::ErrorLocked("{\"status\": \"rsvp_closed\"}"));
+    }
+    if db_main::cancel_reservation(&db.main, &event, user.id).await? {
+        db_main::promote_waitlist(&db.main, &event, now_millis()).await?;
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"no_reservation\"}"))
+    }
+}
+
+async fn events_post_waitlist(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if now_millis() > event.rsvp_cutoff() {
+        return Err(error::ErrorLocked("{\"status\": \"rsvp_closed\"}"));
+    }
+    match db_main::join_waitlist(&db.main, &event, user.id, now_millis()).await? {
+        db_main::WaitlistJoin::Joined(position) => Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(json!({ "status": "waitlisted", "position": position }))),
+        db_main::WaitlistJoin::SeatsAvailable => Err(error::ErrorConflict("{\"status\": \"seats_available\"}")),
+        db_main::WaitlistJoin::AlreadyHeld => Err(error::ErrorConflict("{\"status\": \"already_has_ticket\"}")),
+    }
+}
+
+async fn events_delete_waitlist(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if db_main::leave_waitlist(&db.main, &event, user.id).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"not_waitlisted\"}"))
+    }
+}
+
+// waitlist entries with the account details admins need to recognize people
+async fn waitlist_with_users(db: &web::Data<Databases>, entries: Vec<db_main::WaitlistEntry>) -> Vec<serde_json::Value> {
+    let mut listed = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let holder = db_auth::get_user_id(&db.auth, entry.user_id.to_string()).await.ok();
+        listed.push(json!({
+            "place": index + 1,
+            "user_id": entry.user_id,
+            "student_id": holder.as_ref().map(|holder| holder.student_id.clone()),
+            "username": holder.as_ref().map(|holder| holder.username.clone()),
+            "full_name": holder.as_ref().map(|holder| holder.full_name.clone()),
+            "creation_date": entry.creation_date,
+        }));
+    }
+    listed
+}
+
+async fn manage_get_waitlist(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if !can_manage(&db, &user, event.organization_id).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    let entries = db_main::get_waitlist(&db.main, &event).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(waitlist_with_users(&db, entries).await))
+}
+
+async fn manage_reorder_waitlist(req: HttpRequest, data: web::Json<db_main::WaitlistOrderData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = find_occurrence(&req, &db).await?;
+    if !can_manage(&db, &user, event.organization_id).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    let entries = db_main::reorder_waitlist(&db.main, &event, data.into_inner().user_ids).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(waitlist_with_users(&db, entries).await))
+}
+
+// drop a single date from a recurring event
+async fn manage_cancel_occurrence(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    let occurrence = req.match_info().get("occurrence").and_then(|occurrence| occurrence.parse::<i64>().ok());
+    let event = match occurrence.and_then(|occurrence| event.resolve_occurrence(Some(occurrence), now_millis())) {
+        Some(event) if event.recurring() => event,
+        _ => return Err(error::ErrorBadRequest("{\"status\": \"bad_occurrence\"}")),
+    };
+    db_main::cancel_occurrence(&db.main, &event, now_millis()).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .body("{\"status\": \"success\"}"))
+}
+
+#[derive(Deserialize)]
+struct SurveyQuery {
+    occurrence: Option<i64>,
+}
+
+// the occurrence a survey answer is for: the one asked for, or else the one the user last checked in to. none if they never came
+async fn attended_occurrence(req: &HttpRequest, db: &web::Data<Databases>, user: &db_auth::User) -> Result<Option<db_main::Event>, AWError> {
+    let query = web::Query::<SurveyQuery>::from_query(req.query_string()).map_err(|_| error::ErrorBadRequest("{\"status\": \"bad_occurrence\"}"))?;
+    let event = find_event(db, event_id_param(req)?).await?;
+    if !event.published(now_millis()) {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"));
+    }
+    let attended = db_main::get_event_tickets(&db.main, event.id, query.occurrence)
+        .await?
+        .into_iter()
+        .filter(|ticket| ticket.holder_id == user.id && ticket.status == "checked_in")
+        .max_by_key(|ticket| ticket.occurrence);
+    Ok(attended.map(|ticket| if event.recurring() { event.at_occurrence(ticket.occurrence) } else { event }))
+}
+
+// the survey as a student sees it, with whether they can still answer
+async fn events_get_survey(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let survey = db_main::get_survey(&db.main, event_id_param(&req)?)
+        .await?
+        .ok_or_else(|| error::ErrorNotFound("{\"status\": \"no_survey\"}"))?;
+    let status = match attended_occurrence(&req, &db, &user).await? {
+        Some(event) => survey_status(&db, &survey, &event, user.id).await?,
+        None => "not_attended",
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(json!({ "status": status, "survey": survey })))
+}
+
+// "open" when the user can answer now, otherwise why not
+async fn survey_status(db: &web::Data<Databases>, survey: &survey::Survey, event: &db_main::Event, user_id: i64) -> Result<&'static str, AWError> {
+    let now = now_millis();
+    if now < event.end_time {
+        Ok("not_ended")
+    } else if now > event.end_time.saturating_add(survey.open_days.saturating_mul(86_400_000)) {
+        Ok("closed")
+    } else if db_main::has_responded(&db.main, event.id, event.occurrence, user_id).await? {
+        Ok("responded")
+    } else {
+        Ok("open")
+    }
+}
+
+// only people checked in to the event can answer, once per occurrence, after it ends
+async fn events_post_survey(req: HttpRequest, data: web::Json<survey::ResponseData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let survey = db_main::get_survey(&db.main, event_id_param(&req)?)
+        .await?
+        .ok_or_else(|| error::ErrorNotFound("{\"status\": \"no_survey\"}"))?;
+    let event = attended_occurrence(&req, &db, &user)
+        .await?
+        .ok_or_else(|| error::ErrorForbidden("{\"status\": \"not_attended\"}"))?;
+    match survey_status(&db, &survey, &event, user.id).await? {
+        "open" => {}
+        "responded" => return Err(error::ErrorConflict("{\"status\": \"responded\"}")),
+        status => return Err(error::ErrorForbidden(json!({ "status": status }).to_string())),
+    }
+    let answers = survey::validate_answers(&survey.questions, data.into_inner().answers).map_err(|status| error::ErrorBadRequest(json!({ "status": status }).to_string()))?;
+    let response_id = db_main::submit_survey_response(&db.main, &event, user.id, answers, now_millis())
+        .await?
+        .ok_or_else(|| error::ErrorConflict("{\"status\": \"responded\"}"))?;
+    if survey.bonus_points > 0 && !db_auth::update_points(&db.auth, user.id, survey.bonus_points, "survey", response_id.to_string()).await? {
+        return Err(error::ErrorInternalServerError("{\"status\": \"point_transaction_failed\"}"));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(json!({ "status": "success", "bonus_points": survey.bonus_points })))
+}
+
+async fn manage_set_survey(req: HttpRequest, data: web::Json<survey::SurveySettings>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    if let Err(status) = survey::validate_settings(&data) {
+        return Err(error::ErrorBadRequest(json!({ "status": status, "kinds": survey::KINDS }).to_string()));
+    }
+    if user.data != "admin" && data.bonus_points > officer_max_points() {
+        return Err(error::ErrorBadRequest(json!({ "status": "point_reward_too_high", "max": officer_max_points() }).to_string()));
+    }
+    if !db_main::set_survey(&db.main, event.id, data.into_inner(), now_millis()).await? {
+        return Err(error::ErrorConflict("{\"status\": \"survey_has_responses\"}"));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(db_main::get_survey(&db.main, event.id).await?))
+}
+
+// aggregated answers for organizers. ?occurrence= narrows a recurring event to one date
+async fn manage_get_survey_results(req: HttpRequest, query: web::Query<SurveyQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    let survey = db_main::get_survey(&db.main, event.id)
+        .await?
+        .ok_or_else(|| error::ErrorNotFound("{\"status\": \"no_survey\"}"))?;
+    let attendees = db_main::get_event_tickets(&db.main, event.id, query.occurrence)
+        .await?
+        .iter()
+        .filter(|ticket| ticket.status == "checked_in")
+        .count();
+    let (responses, answers) = db_main::get_survey_answers(&db.main, event.id, query.occurrence).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(survey::results(&survey, attendees, responses, &answers)))
+}
+
+async fn user_get_notifications(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(db_main::get_notifications(&db.main, user.id).await?))
+}
+
+async fn user_post_notifications_read(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    db_main::mark_notifications_read(&db.main, user.id).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .body("{\"status\": \"success\"}"))
+}
+
+async fn tickets_generate_pass(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> impl Responder {
+    let ticket_id = req.match_info().get("ticket_id").unwrap();
+    let ticket_results = db_main::execute_tickets(&db.main, db_main::TicketQuery::GetTicketById, ticket_id.to_string()).await.expect("failed to get ticket");
+    if ticket_results.len() == 1 {
+        if ticket_results[0].holder_id == user.id {
+            let pass_dir = tempdir().expect("tmp dir creation failure");
+            let pass_dir_path = pass_dir.path().to_owned();
+            let corresponding_event = db_main::execute_events(&db.main, db_main::EventQuery::GetEventById, ticket_results[0].event_id as u128).await.expect("failed to get event");
+            // tickets outlive their event while it sits in the trash
+            if corresponding_event.is_empty() {
+                return HttpResponse::BadRequest()
+                    .content_type(ContentType::json())
+                    .body("{\"status\": \"invalid_id\"}");
+            }
+            // passes for recurring events show the occurrence the ticket is for
+            let event = match ticket_results[0].occurrence {
+                0 => corresponding_event[0].clone(),
+                occurrence => corresponding_event[0].at_occurrence(occurrence),
+            };
+            let pass_json = pass::generate_pass_json(ticket_results[0].clone(), event, user);
+            // write pass data
+            fs::write(pass_dir_path.join("pass.json"), &pass_json.to_string()).expect("failed to write pass");
+            // copy images
+            let _ = fs::copy("./passes/background@2x.png", pass_dir_path.join("background@2x.png"));
+            let _ = fs::copy("./passes/icon@2x.png", pass_dir_path.join("icon@2x.png"));
+            let _ = fs::copy("./passes/logo@2x.png", pass_dir_path.join("logo@2x.png"));
+            // make manifest
+            let manifest_json = json!({
+                "pass.json": calculate_hash(pass_dir_path.join("pass.json")),
+                "background@2x.png": calculate_hash(pass_dir_path.join("background@2x.png")),
+                "icon@2x.png": calculate_hash(pass_dir_path.join("icon@2x.png")),
+                "logo@2x.png": calculate_hash(pass_dir_path.join("logo@2x.png")),
+            });
+            fs::write(pass_dir_path.join("manifest.json"), manifest_json.to_string()).expect("failed to write manifest");
+            let _ = sign_pass(&pass_dir_path);
+            let output_dir = tempdir().expect("could not create dir for pass");
+            let pkpass_path = package_pass(&pass_dir_path, output_dir.path().to_path_buf());
+            let pkpass_bytes = fs::read(pkpass_path).expect("Failed to read .pkpass file");
+            HttpResponse::Ok()
+                .content_type("application/vnd.apple.pkpass")
+                .body(pkpass_bytes)
+        } else {
+            HttpResponse::Unauthorized()
+                .content_type(ContentType::json())
+                .body("{\"status\": \"unauthorized\"}")
+        }
+    } else {
+        HttpResponse::BadRequest()
+            .content_type(ContentType::json())
+            .body("{\"status\": \"invalid_id\"}")
+    }
+}
+
+async fn user_generate_pass(user: db_auth::User) -> impl Responder {
+    let pass_dir = tempdir().expect("tmp dir creation failure");
+    let pass_dir_path = pass_dir.path().to_owned();
+    let pass_json = pass::generate_id_json(user);
+    // write pass data
+    fs::write(pass_dir_path.join("pass.json"), &pass_json.to_string()).expect("failed to write pass");
+    // copy images
+    let _ = fs::copy("./passes/background@2x.png", pass_dir_path.join("background@2x.png"));
+    let _ = fs::copy("./passes/icon@2x.png", pass_dir_path.join("icon@2x.png"));
+    let _ = fs::copy("./passes/logo@2x.png", pass_dir_path.join("logo@2x.png"));
+    // make manifest
+    let manifest_json = json!({
+        "pass.json": calculate_hash(pass_dir_path.join("pass.json")),
+        "background@2x.png": calculate_hash(pass_dir_path.join("background@2x.png")),
+        "icon@2x.png": calculate_hash(pass_dir_path.join("icon@2x.png")),
+        "logo@2x.png": calculate_hash(pass_dir_path.join("logo@2x.png")),
+    });
+    fs::write(pass_dir_path.join("manifest.json"), manifest_json.to_string()).expect("failed to write manifest");
+    let _ = sign_pass(&pass_dir_path);
+    let output_dir = tempdir().expect("could not create dir for pass");
+    let pkpass_path = package_pass(&pass_dir_path, output_dir.path().to_path_buf());
+    let pkpass_bytes = fs::read(pkpass_path).expect("Failed to read .pkpass file");
+    HttpResponse::Ok()
+        .content_type("application/vnd.apple.pkpass")
+        .body(pkpass_bytes)
+}
+
+// part of pass creation
+fn calculate_hash(file_path: PathBuf) -> String {
+    let content = fs::read(&file_path).expect("failed to read pass file");
+    let digest = openssl::hash::hash(MessageDigest::sha1(), &content).expect("failed to has pass file");
+    let hex_digest = digest.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join("");
+    hex_digest
+}
+fn sign_pass(pass_dir_path: &PathBuf) -> PathBuf {
+    let manifest_json_path = pass_dir_path.join("manifest.json");
+    let manifest_json_data = fs::read(&manifest_json_path)
+        .expect("failed to read manifest.json file");
+
+    let pkcs12_file = fs::read("./passes/certs/Certificates.p12")
+        .expect("failed to read PKCS #12 file");
+    let pkcs12 = Pkcs12::from_der(&pkcs12_file)
+        .expect("failed to parse PKCS #12 file");
+
+    let pkcs12_data = pkcs12.parse2("")
+        .expect("failed to parse PKCS #12 data");
+
+    let pkcs7 = openssl::pkcs7::Pkcs7::sign(&pkcs12_data.cert.unwrap(), &pkcs12_data.pkey.unwrap(), &pkcs12_data.ca.unwrap_or(Stack::new().unwrap()), &manifest_json_data, Pkcs7Flags::empty())
+        .expect("failed to sign manifest.json");
+
+    let signature_path = pass_dir_path.join("signature");
+    
+    let pkcs7_der = pkcs7.to_der()
+        .expect("failed to serialize PKCS #7 to DER");
+    fs::write(pass_dir_path.join("signature"), pkcs7_der)
+        .expect("failed to write PKCS #7 signature to file");
+
+    signature_path
+}
+fn package_pass(pass_dir_path: &PathBuf, output_dir: PathBuf) -> PathBuf {
+    let status = std::process::Command::new("zip")
+        .args(&["-r", "-q", "-0", "-X", output_dir.join("pass.pkpass").to_str().unwrap(), "."])
+        .current_dir(&pass_dir_path)
+        .status()
+        .expect("failed to execute zip");
+
+    if !status.success() {
+        panic!("failed to package pass");
+    }
+
+    output_dir.join("pass.pkpass")
+}
+// end pass creation extras
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    confirm: Option<String>,
+}
+
+// deleting moves events to the trash. ?confirm=permanent skips it and removes the event and its tickets right away, for admins only
+async fn manage_delete_event(req: HttpRequest, query: web::Query<DeleteQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if query.confirm.as_deref() == Some("permanent") {
+        if user.data != "admin" {
+            return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+        }
+        return Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(db_main::delete_event(&db.main, event_id_param(&req)?.to_string()).await?));
+    }
+    let event = managed_event(&req, &db, &user).await?;
+    let now = now_millis();
+    if db_main::trash_event(&db.main, event.id, now).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(json!({ "status": 200, "purge_at": now + db_main::retention_days() * 86_400_000 })))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+    }
+}
+
+async fn manage_get_trash(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let page = query.page(&db_main::EVENT_SORTS, "-start_time")?;
+        let retention = db_main::retention_days() * 86_400_000;
+        let trash = page.apply(db_main::get_trash(&db.main).await?, |event, sort| (db_main::event_sort_key(event, sort), paging::Key::Int(event.id)));
+        let items: Vec<serde_json::Value> = trash
+            .items
+            .into_iter()
+            .map(|event| {
+                let purge_at = event.deleted_at.unwrap_or_default() + retention;
+                json!({ "event": event, "purge_at": purge_at })
+            })
+            .collect();
+        Ok(paging::respond(paging::Paged { items, total: trash.total, next_cursor: trash.next_cursor }, "no-cache"))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_restore_event(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    // trashed events aren't found by find_event, so ownership is checked here
+    let event = db_main::get_event_any(&db.main, event_id_param(&req)?)
+        .await?
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"not_in_trash\"}"))?;
+    if !can_manage(&db, &user, event.organization_id).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if db_main::restore_event(&db.main, event.id).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"not_in_trash\"}"))
+    }
+}
+
+async fn manage_update_event_status(req: HttpRequest, data: web::Json<db_main::EventStatusData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    if let Err(status) = db_main::validate_status(&data.status, data.publish_at) {
+        return Err(error::ErrorBadRequest(json!({ "status": status }).to_string()));
+    }
+    // cancelling always voids tickets and notifies holders, whichever endpoint it comes through
+    if data.status == "cancelled" {
+        let cancellation = db_main::EventCancelData { reason: "".to_string(), reverse_points: false };
+        return Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(cancel_event(&db, &event, cancellation).await?));
+    }
+    if db_main::update_event_status(&db.main, event.id, data.into_inner()).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+    }
+}
+
+// cancels an event, voiding its tickets. points from check-ins are taken back when reverse_points is set
+async fn cancel_event(db: &web::Data<Databases>, event: &db_main::Event, data: db_main::EventCancelData) -> Result<serde_json::Value, AWError> {
+    let voided = db_main::cancel_event(&db.main, event, data.reason, now_millis()).await?;
+    let mut reversed = 0;
+    if data.reverse_points {
+        for ticket in voided.iter().filter(|ticket| ticket.status == "checked_in") {
+            reversed += db_auth::reverse_award(&db.auth, ticket.holder_id, ticket.id.to_string(), event.point_reward).await?;
+        }
+    }
+    Ok(json!({ "status": "cancelled", "voided": voided.len(), "points_reversed": reversed }))
+}
+
+async fn manage_cancel_event(req: HttpRequest, data: web::Json<db_main::EventCancelData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    if event.status == "cancelled" {
+        return Err(error::ErrorConflict("{\"status\": \"already_cancelled\"}"));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(cancel_event(&db, &event, data.into_inner()).await?))
+}
+
+#[derive(Deserialize)]
+struct ImageQuery {
+    event_id: Option<i64>,
+}
+
+// multipart upload with an "image" field. ?event_id= also sets it as that event's image. officers of any organization can upload
+async fn manage_upload_image(payload: actix_multipart::Multipart, query: web::Query<ImageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let allowed = match query.event_id {
+        Some(event_id) => can_manage(&db, &user, find_event(&db, event_id).await?.organization_id).await?,
+        None => user.data == "admin" || db_main::get_memberships(&db.main, user.id).await?.iter().any(|membership| membership.role == "officer"),
+    };
+    if !allowed {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    let stored = images::store(images::read_upload(payload).await?).await?;
+    if let Some(event_id) = query.event_id {
+        if !db_main::set_event_image(&db.main, event_id, stored.image.clone()).await? {
+            return Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"));
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(stored))
+}
+
+async fn images_get(req: HttpRequest) -> Result<HttpResponse, AWError> {
+    images::serve(req.match_info().get("name").unwrap_or_default())
+}
+
+#[derive(Deserialize)]
+struct AttendanceQuery {
+    format: Option<String>,
+    occurrence: Option<i64>,
+}
+
+// who came to an event. ?format=csv for a spreadsheet, ?occurrence= to narrow a recurring event to one date
+async fn manage_get_attendance(req: HttpRequest, query: web::Query<AttendanceQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    // one-off events are always themselves, like find_occurrence. without an occurrence a recurring event reports every ticket
+    let (event, occurrence) = match query.occurrence {
+        Some(occurrence) if event.recurring() => (
+            event
+                .resolve_occurrence(Some(occurrence), now_millis())
+                .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_occurrence\"}"))?,
+            Some(occurrence),
+        ),
+        _ => (event, None),
+    };
+    let tickets = db_main::get_event_tickets(&db.main, event.id, occurrence).await?;
+    let mut holders: Vec<i64> = tickets.iter().map(|ticket| ticket.holder_id).collect();
+    holders.sort_unstable();
+    holders.dedup();
+    let users = db_auth::get_users(&db.auth, holders).await?;
+    let report = attendance::report(&event, tickets, &users, now_millis());
+    if query.format.as_deref() == Some("csv") {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .insert_header(("Content-Disposition", format!("attachment; filename=\"attendance-{}.csv\"", event.id)))
+            .content_type("text/csv; charset=utf-8")
+            .body(attendance::to_csv(&report)))
+    } else {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(report))
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    commit: Option<bool>,
+    point_reward: Option<i64>,
+}
+
+// upload an .ics file. previews the changes unless ?commit=true
+async fn manage_import_events(body: web::Bytes, query: web::Query<ImportQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let body = std::str::from_utf8(&body).map_err(|_| error::ErrorBadRequest("{\"status\": \"bad_calendar\"}"))?;
+        let (events, skipped) = ical::parse(body);
+        let point_reward = query.point_reward.unwrap_or_else(|| env::var("IMPORT_POINT_REWARD").ok().and_then(|value| value.parse::<i64>().ok()).unwrap_or(0));
+        let commit = query.commit.unwrap_or(false);
+        let changes = db_main::import_events(&db.main, events, point_reward, commit).await?;
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(json!({ "committed": commit, "point_reward": point_reward, "changes": changes, "skipped": skipped })))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+// officers create events for their own organization, with rewards capped at OFFICER_MAX_POINTS
+async fn manage_create_event(data: web::Json<db_main::EventCreateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    validate_new_event(&db, &user, &data).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(db_main::execute_insert(&db.main, data).await?)
+    )
+}
+
+// checks shared by every way of making an event
+async fn validate_new_event(db: &web::Data<Databases>, user: &db_auth::User, data: &db_main::EventCreateData) -> Result<(), AWError> {
+    if !can_manage(db, user, data.organization_id).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if user.data != "admin" && data.point_reward > officer_max_points() {
+        return Err(error::ErrorBadRequest(json!({ "status": "point_reward_too_high", "max": officer_max_points() }).to_string()));
+    }
+    if !checkin::window_valid(data.checkin_opens_before, data.checkin_closes_after) {
+        return Err(error::ErrorBadRequest(checkin::window_error().to_string()));
+    }
+    if user.data != "admin" && !checkin::officer_window_allowed(data.checkin_opens_before, data.checkin_closes_after) {
+        return Err(error::ErrorBadRequest(checkin::officer_window_error().to_string()));
+    }
+    if let Err(status) = db_main::validate_status(&data.status, data.publish_at) {
+        return Err(error::ErrorBadRequest(json!({ "status": status }).to_string()));
+    }
+    if !checkin::MODES.contains(&data.self_checkin.as_str()) {
+        return Err(error::ErrorBadRequest(json!({ "status": "bad_mode", "modes": checkin::MODES }).to_string()));
+    }
+    if !data.category.is_empty() && !db_main::CATEGORIES.contains(&data.category.to_lowercase().as_str()) {
+        return Err(error::ErrorBadRequest(json!({ "status": "bad_category", "categories": db_main::CATEGORIES }).to_string()));
+    }
+    if !data.rrule.is_empty() {
+        if let Err(reason) = recurrence::parse(&data.rrule) {
+            return Err(error::ErrorBadRequest(json!({ "status": "bad_rrule", "reason": reason }).to_string()));
+        }
+    }
+    if !timezone::valid(&data.timezone) {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_timezone\"}"));
+    }
+    if !timezone::time_in_range(data.start_time) || !timezone::time_in_range(data.end_time) || data.start_time > data.end_time {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_time\"}"));
+    }
+    Ok(())
+}
+
+// copies an event to a new date
+async fn manage_duplicate_event(req: HttpRequest, data: web::Json<db_main::EventDuplicateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    let duplicate = event.duplicate(data.into_inner());
+    validate_new_event(&db, &user, &duplicate).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(json!({ "status": "success", "id": db_main::insert_event(&db.main, duplicate).await? })))
+}
+
+fn template_id_param(req: &HttpRequest) -> Result<i64, AWError> {
+    req.match_info()
+        .get("template_id")
+        .and_then(|template_id| template_id.parse::<i64>().ok())
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_template_id\"}"))
+}
+
+// the template in the path, if the user may use it. templates without an organization are for admins
+async fn managed_template(req: &HttpRequest, db: &web::Data<Databases>, user: &db_auth::User) -> Result<db_main::EventTemplate, AWError> {
+    let template = db_main::get_template(&db.main, template_id_param(req)?)
+        .await?
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_template_id\"}"))?;
+    if can_manage(db, user, template.organization_id).await? {
+        Ok(template)
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_create_template(data: web::Json<db_main::EventTemplateCreateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if !can_manage(&db, &user, data.organization_id).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if data.name.trim().is_empty() {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_name\"}"));
+    }
+    if user.data != "admin" && data.fields.point_reward.is_some_and(|point_reward| point_reward > officer_max_points()) {
+        return Err(error::ErrorBadRequest(json!({ "status": "point_reward_too_high", "max": officer_max_points() }).to_string()));
+    }
+    if !checkin::window_valid(data.fields.checkin_opens_before, data.fields.checkin_closes_after) {
+        return Err(error::ErrorBadRequest(checkin::window_error().to_string()));
+    }
+    if user.data != "admin" && !checkin::officer_window_allowed(data.fields.checkin_opens_before, data.fields.checkin_closes_after) {
+        return Err(error::ErrorBadRequest(checkin::officer_window_error().to_string()));
+    }
+    if data.fields.self_checkin.as_ref().is_some_and(|mode| !checkin::MODES.contains(&mode.as_str())) {
+        return Err(error::ErrorBadRequest(json!({ "status": "bad_mode", "modes": checkin::MODES }).to_string()));
+    }
+    if data.fields.category.as_ref().is_some_and(|category| !category.is_empty() && !db_main::CATEGORIES.contains(&category.to_lowercase().as_str())) {
+        return Err(error::ErrorBadRequest(json!({ "status": "bad_category", "categories": db_main::CATEGORIES }).to_string()));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(json!({ "status": "success", "id": db_main::create_template(&db.main, data.into_inner(), now_millis()).await? })))
+}
+
+// admins see every template, officers those of their organizations
+async fn manage_get_templates(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let organization_ids = if user.data == "admin" {
+        None
+    } else {
+        let memberships = db_main::get_memberships(&db.main, user.id).await?;
+        let officer_of: Vec<i64> = memberships.iter().filter(|membership| membership.role == "officer").map(|membership| membership.organization_id).collect();
+        if officer_of.is_empty() {
+            return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+        }
+        Some(officer_of)
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(db_main::get_templates(&db.main, organization_ids).await?))
+}
+
+async fn manage_delete_template(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let template = managed_template(&req, &db, &user).await?;
+    db_main::delete_template(&db.main, template.id).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .body("{\"status\": \"success\"}"))
+}
+
+async fn manage_create_from_template(req: HttpRequest, data: web::Json<db_main::EventFromTemplateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let template = managed_template(&req, &db, &user).await?;
+    let event = template
+        .event(data.into_inner())
+        .map_err(|missing| error::ErrorBadRequest(json!({ "status": "missing_fields", "fields": missing }).to_string()))?;
+    validate_new_event(&db, &user, &event).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(json!({ "status": "success", "id": db_main::insert_event(&db.main, event).await? })))
+}
+
+fn officer_max_points() -> i64 {
+    env::var("OFFICER_MAX_POINTS").ok().and_then(|value| value.parse::<i64>().ok()).unwrap_or(10)
+}
+
+// partial updates to an event's details. status, check-in and images have their own endpoints
+async fn manage_edit_event(req: HttpRequest, data: web::Json<db_main::EventEditData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let event = managed_event(&req, &db, &user).await?;
+    if user.data != "admin" && data.point_reward.is_some_and(|point_reward| point_reward > officer_max_points()) {
+        return Err(error::ErrorBadRequest(json!({ "status": "point_reward_too_high", "max": officer_max_points() }).to_string()));
+    }
+    if let Some(category) = &data.category {
+        if !category.is_empty() && !db_main::CATEGORIES.contains(&category.to_lowercase().as_str()) {
+            return Err(error::ErrorBadRequest(json!({ "status": "bad_category", "categories": db_main::CATEGORIES }).to_string()));
+        }
+    }
+    if let Some(rrule) = &data.rrule {
+        if !rrule.is_empty() {
+            if let Err(reason) = recurrence::parse(rrule) {
+                return Err(error::ErrorBadRequest(json!({ "status": "bad_rrule", "reason": reason }).to_string()));
+            }
+        }
+    }
+    if data.timezone.as_ref().is_some_and(|zone| !timezone::valid(zone)) {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_timezone\"}"));
+    }
+    let (start_time, end_time) = (data.start_time.unwrap_or(event.start_time), data.end_time.unwrap_or(event.end_time));
+    if !timezone::time_in_range(start_time) || !timezone::time_in_range(end_time) || start_time > end_time {
+        return Err(error::ErrorBadRequest("{\"status\": \"bad_time\"}"));
+    }
+    let capacity_raised = match (event.capacity, data.capacity) {
+        (Some(old), Some(Some(new))) => new > old,
+        (Some(_), Some(None)) => true,
+        _ => false,
+    };
+    if db_main::edit_event(&db.main, event.id, data.into_inner()).await? {
+        let event = find_event(&db, event.id).await?;
+        if capacity_raised {
+            db_main::promote_waitlists(&db.main, &event, now_millis()).await?;
+        }
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(event))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+    }
+}
+
+fn org_id_param(req: &HttpRequest) -> Result<i64, AWError> {
+    req.match_info()
+        .get("org_id")
+        .and_then(|org_id| org_id.parse::<i64>().ok())
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_org_id\"}"))
+}
+
+async fn manage_create_org(data: web::Json<db_main::OrganizationCreateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let name = data.into_inner().name.trim().to_string();
+        if name.is_empty() {
+            return Err(error::ErrorBadRequest("{\"status\": \"bad_name\"}"));
+        }
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(db_main::create_organization(&db.main, name, now_millis()).await?))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_get_orgs(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(db_main::get_organizations(&db.main).await?))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_get_org_members(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let org_id = org_id_param(&req)?;
+    if !can_manage(&db, &user, Some(org_id)).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    let members = db_main::get_members(&db.main, org_id).await?;
+    let users = db_auth::get_users(&db.auth, members.iter().map(|member| member.user_id).collect()).await?;
+    let listed: Vec<serde_json::Value> = members
+        .iter()
+        .map(|member| {
+            let member_user = users.get(&member.user_id);
+            json!({
+                "user_id": member.user_id,
+                "student_id": member_user.map(|member_user| member_user.student_id.clone()),
+                "full_name": member_user.map(|member_user| member_user.full_name.clone()),
+                "role": member.role,
+                "creation_date": member.creation_date,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(listed))
+}
+
+// officers can add members, only admins can appoint officers
+async fn manage_set_org_member(req: HttpRequest, data: web::Json<db_main::MembershipData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let org_id = org_id_param(&req)?;
+    if !can_manage(&db, &user, Some(org_id)).await? || (data.role != "member" && user.data != "admin") {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if !db_main::ROLES.contains(&data.role.as_str()) {
+        return Err(error::ErrorBadRequest(json!({ "status": "bad_role", "roles": db_main::ROLES }).to_string()));
+    }
+    let data = data.into_inner();
+    let member = db_auth::get_user_student_id(&db.auth, data.student_id).await?;
+    // an officer demoting another officer would be an appointment in reverse
+    if user.data != "admin" && db_main::get_role(&db.main, org_id, member.id).await?.as_deref() == Some("officer") {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if db_main::set_member(&db.main, org_id, member.id, data.role, now_millis()).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"bad_org_id\"}"))
+    }
+}
+
+async fn manage_remove_org_member(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let org_id = org_id_param(&req)?;
+    let member_id = req
+        .match_info()
+        .get("user_id")
+        .and_then(|user_id| user_id.parse::<i64>().ok())
+        .ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_user_id\"}"))?;
+    if !can_manage(&db, &user, Some(org_id)).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if user.data != "admin" && db_main::get_role(&db.main, org_id, member_id).await?.as_deref() == Some("officer") {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    if db_main::remove_member(&db.main, org_id, member_id).await? {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body("{\"status\": \"success\"}"))
+    } else {
+        Err(error::ErrorBadRequest("{\"status\": \"not_a_member\"}"))
+    }
+}
+
+// drafts included, so officers can find what they haven't published yet
+async fn manage_get_org_events(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let org_id = org_id_param(&req)?;
+    if !can_manage(&db, &user, Some(org_id)).await? {
+        return Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(db_main::get_organization_events(&db.main, org_id).await?))
+}
+
+async fn user_get_orgs(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    let memberships = db_main::get_memberships(&db.main, user.id).await?;
+    let organizations = db_main::get_organizations(&db.main).await?;
+    let listed: Vec<serde_json::Value> = memberships
+        .iter()
+        .filter_map(|membership| {
+            let organization = organizations.iter().find(|organization| organization.id == membership.organization_id)?;
+            Some(json!({ "id": organization.id, "name": organization.name, "role": membership.role }))
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-cache"))
+        .json(listed))
+}
+
+async fn manage_create_invite(data: web::Json<db_auth::InviteCreateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(db_auth::create_invite(&db.auth, data, user.id).await?)
+        )
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_get_invites(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let page = query.page(&db_auth::INVITE_SORTS, "-creation_date")?;
+        let invites = page.apply(db_auth::get_invites(&db.auth).await?, db_auth::invite_sort_key);
+        Ok(paging::respond(invites, "no-cache"))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_delete_invite(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(db_auth::delete_invite(&db.auth, req.match_info().get("code").unwrap().to_string()).await?)
+        )
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_get_hash_report(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(db_auth::get_hash_report(&db.auth).await?)
+        )
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+fn moderation_target(req: &HttpRequest, user: &db_auth::User) -> Result<i64, AWError> {
+    match req.match_info().get("user_id").unwrap().parse::<i64>() {
+        Ok(target_id) if target_id != user.id => Ok(target_id),
+        _ => Err(error::ErrorBadRequest("{\"status\": \"bad_user_id\"}")),
+    }
 }
 
-async fn events_get_all(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+async fn manage_suspend_user(req: HttpRequest, data: web::Json<db_auth::SuspendData>, db: web::Data<Databases>, session: web::Data<RwLock<Sessions>>, user: db_auth::User) -> Result<HttpResponse, AWError> {
     if user.data == "admin" {
+        let target_id = moderation_target(&req, &user)?;
+        let result = db_auth::execute_moderation(&db.auth, db_auth::Moderation::Suspend(data.reason.clone()), target_id).await?;
+        auth::end_user_sessions(&session, target_id);
         Ok(HttpResponse::Ok()
             .insert_header(("Cache-Control", "no-cache"))
-            .json(db_main::execute_events(&db.main, db_main::EventQuery::GetAllEvents, 0).await?)
+            .body(result)
         )
     } else {
         Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
 }
 
-async fn events_get_future(db: web::Data<Databases>) -> Result<HttpResponse, AWError> {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("time just went fucking backwards");
-
-    Ok(HttpResponse::Ok()
-        .insert_header(("Cache-Control", "max-age=150"))
-        .json(db_main::execute_events(&db.main, db_main::EventQuery::GetFutureEvents, since_the_epoch.as_millis()).await?)
-    )
-}
-
-async fn tickets_get_all(db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+async fn manage_unsuspend_user(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
     if user.data == "admin" {
+        let target_id = moderation_target(&req, &user)?;
         Ok(HttpResponse::Ok()
             .insert_header(("Cache-Control", "no-cache"))
-            .json(db_main::execute_tickets(&db.main, db_main::TicketQuery::GetAllTickets, "".to_string()).await?))
+            .body(db_auth::execute_moderation(&db.auth, db_auth::Moderation::Unsuspend, target_id).await?)
+        )
     } else {
         Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
 }
 
-async fn tickets_create_ticket(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+async fn manage_rename_user(req: HttpRequest, data: web::Json<db_auth::RenameData>, db: web::Data<Databases>, session: web::Data<RwLock<Sessions>>, user: db_auth::User) -> Result<HttpResponse, AWError> {
     if user.data == "admin" {
-        let event = db_main::execute_events(&db.main, db_main::EventQuery::GetEventById, req.match_info().get("event_id").unwrap().parse::<i64>().unwrap() as u128).await?;
-        let student_id = req.match_info().get("user_id").unwrap();
-        let target_user = db_auth::get_user_student_id(&db.auth, student_id.to_string()).await?;
-        if event.len() == 1 {
-            let start = SystemTime::now();
-            let since_the_epoch = start
-                .duration_since(UNIX_EPOCH)
-                .expect("time just went fucking backwards");
-            let owned_tickets = db_main::execute_tickets(&db.main, db_main::TicketQuery::GetUserEventTickets, format!("{}_{}", target_user.id, event[0].id)).await?;
-            if owned_tickets.len() == 0 {
-                let point_deduction = db_auth::update_points(&db.auth, target_user.id, event[0].point_reward).await?;
-                if point_deduction {
-                    Ok(HttpResponse::Ok()
-                        .insert_header(("Cache-Control", "no-cache"))
-                        .json(db_main::create_ticket(&db.main, event[0].id, target_user.id, since_the_epoch.as_millis()).await?))
-                } else {
-                    Err(error::ErrorInternalServerError("{\"status\": \"point_transaction_failed\"}"))
-                }
-            } else {
-                Err(error::ErrorLocked("{\"status\": \"ticket_sale_ended\"}"))
-            }
-        } else {
-            Err(error::ErrorBadRequest("{\"status\": \"bad_event_id\"}"))
+        let target_id = moderation_target(&req, &user)?;
+        if !auth::field_allowed(&data.username) {
+            return Err(error::ErrorBadRequest("{\"status\": \"bad_username\"}"));
         }
-
+        // the admin renaming is the reviewer, so only blocked names are refused
+        if matches!(filter::check_name(&data.username), filter::Verdict::Blocked(_)) && !data.override_filter {
+            return Err(error::ErrorBadRequest("{\"status\": \"name_blocked\"}"));
+        }
+        if db_auth::get_user_username(&db.auth, data.username.clone()).await.is_ok() {
+            return Err(error::ErrorConflict("{\"status\": \"username_taken\"}"));
+        }
+        let result = db_auth::execute_moderation(&db.auth, db_auth::Moderation::Rename(data.username.clone()), target_id).await?;
+        // sessions are keyed by username, so the old ones can't be used anymore
+        auth::end_user_sessions(&session, target_id);
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(result)
+        )
     } else {
         Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
 }
 
-async fn tickets_generate_pass(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> impl Responder {
-    let ticket_id = req.match_info().get("ticket_id").unwrap();
-    let ticket_results = db_main::execute_tickets(&db.main, db_main::TicketQuery::GetTicketById, ticket_id.to_string()).await.expect("failed to get ticket");
-    if ticket_results.len() == 1 {
-        if ticket_results[0].holder_id == user.id {
-            let pass_dir = tempdir().expect("tmp dir creation failure");
-            let pass_dir_path = pass_dir.path().to_owned();
-            let corresponding_event = db_main::execute_events(&db.main, db_main::EventQuery::GetEventById, ticket_results[0].event_id as u128).await.expect("failed to get event");
-            let pass_json = pass::generate_pass_json(ticket_results[0].clone(), corresponding_event[0].clone(), user);
-            // write pass data
-            fs::write(pass_dir_path.join("pass.json"), &pass_json.to_string()).expect("failed to write pass");
-            // copy images
-            let _ = fs::copy("./passes/background@2x.png", pass_dir_path.join("background@2x.png"));
-            let _ = fs::copy("./passes/icon@2x.png", pass_dir_path.join("icon@2x.png"));
-            let _ = fs::copy("./passes/logo@2x.png", pass_dir_path.join("logo@2x.png"));
-            // make manifest
-            let manifest_json = json!({
-                "pass.json": calculate_hash(pass_dir_path.join("pass.json")),
-                "background@2x.png": calculate_hash(pass_dir_path.join("background@2x.png")),
-                "icon@2x.png": calculate_hash(pass_dir_path.join("icon@2x.png")),
-                "logo@2x.png": calculate_hash(pass_dir_path.join("logo@2x.png")),
-            });
-            fs::write(pass_dir_path.join("manifest.json"), manifest_json.to_string()).expect("failed to write manifest");
-            let _ = sign_pass(&pass_dir_path);
-            let output_dir = tempdir().expect("could not create dir for pass");
-            let pkpass_path = package_pass(&pass_dir_path, output_dir.path().to_path_buf());
-            let pkpass_bytes = fs::read(pkpass_path).expect("Failed to read .pkpass file");
-            HttpResponse::Ok()
-                .content_type("application/vnd.apple.pkpass")
-                .body(pkpass_bytes)
-        } else {
-            HttpResponse::Unauthorized()
-                .content_type(ContentType::json())
-                .body("{\"status\": \"unauthorized\"}")
-        }
+async fn manage_hide_user(req: HttpRequest, data: web::Json<db_auth::HideData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let target_id = moderation_target(&req, &user)?;
+        Ok(HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(db_auth::execute_moderation(&db.auth, db_auth::Moderation::Hide(data.hidden), target_id).await?)
+        )
     } else {
-        HttpResponse::BadRequest()
-            .content_type(ContentType::json())
-            .body("{\"status\": \"invalid_id\"}")
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
 }
 
-async fn user_generate_pass(user: db_auth::User) -> impl Responder {
-    let pass_dir = tempdir().expect("tmp dir creation failure");
-    let pass_dir_path = pass_dir.path().to_owned();
-    let pass_json = pass::generate_id_json(user);
-    // write pass data
-    fs::write(pass_dir_path.join("pass.json"), &pass_json.to_string()).expect("failed to write pass");
-    // copy images
-    let _ = fs::copy("./passes/background@2x.png", pass_dir_path.join("background@2x.png"));
-    let _ = fs::copy("./passes/icon@2x.png", pass_dir_path.join("icon@2x.png"));
-    let _ = fs::copy("./passes/logo@2x.png", pass_dir_path.join("logo@2x.png"));
-    // make manifest
-    let manifest_json = json!({
-        "pass.json": calculate_hash(pass_dir_path.join("pass.json")),
-        "background@2x.png": calculate_hash(pass_dir_path.join("background@2x.png")),
-        "icon@2x.png": calculate_hash(pass_dir_path.join("icon@2x.png")),
-        "logo@2x.png": calculate_hash(pass_dir_path.join("logo@2x.png")),
-    });
-    fs::write(pass_dir_path.join("manifest.json"), manifest_json.to_string()).expect("failed to write manifest");
-    let _ = sign_pass(&pass_dir_path);
-    let output_dir = tempdir().expect("could not create dir for pass");
-    let pkpass_path = package_pass(&pass_dir_path, output_dir.path().to_path_buf());
-    let pkpass_bytes = fs::read(pkpass_path).expect("Failed to read .pkpass file");
-    HttpResponse::Ok()
-        .content_type("application/vnd.apple.pkpass")
-        .body(pkpass_bytes)
-}
-
-// part of pass creation
-fn calculate_hash(file_path: PathBuf) -> String {
-    let content = fs::read(&file_path).expect("failed to read pass file");
-    let digest = openssl::hash::hash(MessageDigest::sha1(), &content).expect("failed to has pass file");
-    let hex_digest = digest.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join("");
-    hex_digest
-}
-fn sign_pass(pass_dir_path: &PathBuf) -> PathBuf {
-    let manifest_json_path = pass_dir_path.join("manifest.json");
-    let manifest_json_data = fs::read(&manifest_json_path)
-        .expect("failed to read manifest.json file");
-
-    let pkcs12_file = fs::read("./passes/certs/Certificates.p12")
-        .expect("failed to read PKCS #12 file");
-    let pkcs12 = Pkcs12::from_der(&pkcs12_file)
-        .expect("failed to parse PKCS #12 file");
-
-    let pkcs12_data = pkcs12.parse2("")
-        .expect("failed to parse PKCS #12 data");
-
-    let pkcs7 = openssl::pkcs7::Pkcs7::sign(&pkcs12_data.cert.unwrap(), &pkcs12_data.pkey.unwrap(), &pkcs12_data.ca.unwrap_or(Stack::new().unwrap()), &manifest_json_data, Pkcs7Flags::empty())
-        .expect("failed to sign manifest.json");
-
-    let signature_path = pass_dir_path.join("signature");
-    
-    let pkcs7_der = pkcs7.to_der()
-        .expect("failed to serialize PKCS #7 to DER");
-    fs::write(pass_dir_path.join("signature"), pkcs7_der)
-        .expect("failed to write PKCS #7 signature to file");
-
-    signature_path
+async fn user_post_profile(db: web::Data<Databases>, session: web::Data<RwLock<Sessions>>, identity: Identity, user: db_auth::User, data: web::Json<auth::ProfileForm>) -> Result<HttpResponse, AWError> {
+    auth::update_profile(&db.auth, session, identity, user, data).await
 }
-fn package_pass(pass_dir_path: &PathBuf, output_dir: PathBuf) -> PathBuf {
-    let status = std::process::Command::new("zip")
-        .args(&["-r", "-q", "-0", "-X", output_dir.join("pass.pkpass").to_str().unwrap(), "."])
-        .current_dir(&pass_dir_path)
-        .status()
-        .expect("failed to execute zip");
 
-    if !status.success() {
-        panic!("failed to package pass");
+async fn manage_get_name_reviews(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let page = query.page(&db_auth::NAME_REVIEW_SORTS, "creation_date")?;
+        let reviews = page.apply(db_auth::get_name_reviews(&db.auth).await?, db_auth::name_review_sort_key);
+        Ok(paging::respond(reviews, "no-cache"))
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
-
-    output_dir.join("pass.pkpass")
 }
-// end pass creation extras
 
-async fn manage_delete_event(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+async fn manage_resolve_name_review(req: HttpRequest, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
     if user.data == "admin" {
+        let review_id = req.match_info().get("review_id").unwrap().parse::<i64>().map_err(|_| error::ErrorBadRequest("{\"status\": \"bad_review_id\"}"))?;
+        let approved = req.match_info().get("decision").unwrap() == "approve";
         Ok(HttpResponse::Ok()
             .insert_header(("Cache-Control", "no-cache"))
-            .body(db_main::delete_event(&db.main, req.match_info().get("event_id").unwrap().to_string()).await?)
+            .body(db_auth::resolve_name_review(&db.auth, review_id, approved).await?)
         )
     } else {
         Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
 }
 
-async fn manage_create_event(data: web::Json<db_main::EventCreateData>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+async fn manage_start_impersonation(req: HttpRequest, db: web::Data<Databases>, session: web::Data<RwLock<Sessions>>, identity: Identity, user: db_auth::User) -> Result<HttpResponse, AWError> {
     if user.data == "admin" {
-        Ok(HttpResponse::Ok()
-            .insert_header(("Cache-Control", "no-cache"))
-            .body(db_main::execute_insert(&db.main, data).await?)
-        )
+        impersonate::start(&db, session, identity, user, req.match_info().get("user_id").unwrap().to_string()).await
+    } else {
+        Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
+    }
+}
+
+async fn manage_end_impersonation(db: web::Data<Databases>, session: web::Data<RwLock<Sessions>>, identity: Identity) -> Result<HttpResponse, AWError> {
+    impersonate::end(&db, session, identity).await
+}
+
+async fn manage_get_audit_log(query: web::Query<paging::PageQuery>, db: web::Data<Databases>, user: db_auth::User) -> Result<HttpResponse, AWError> {
+    if user.data == "admin" {
+        let page = query.page(&db_auth::AUDIT_SORTS, "-id")?;
+        Ok(paging::respond(db_auth::get_audit_log(&db.auth, page).await?, "no-cache"))
     } else {
         Err(error::ErrorUnauthorized("{\"status\": \"unauthorized\"}"))
     }
@@ -407,13 +1483,14 @@ async fn main() -> io::Result<()> {
     dotenv().ok();
 
     // hashmap w: web::Data<RwLock<Sessions>>ith user sessions in it
-    let sessions: web::Data<RwLock<Sessions>> = web::Data::new(RwLock::new(Sessions { user_map: HashMap::new() }));
+    let sessions: web::Data<RwLock<Sessions>> = web::Data::new(RwLock::new(Sessions { user_map: HashMap::new(), impersonations: HashMap::new() }));
 
     // auth database connection
     let auth_db_manager = SqliteConnectionManager::file("data_auth.db");
     let auth_db_pool = db_auth::Pool::new(auth_db_manager).unwrap();
     let auth_db_connection = auth_db_pool.get().expect("auth db: connection failed");
     auth_db_connection.execute_batch("PRAGMA journal_mode=WAL;").expect("auth db: WAL failed");
+    db_schema::migrate_auth(&auth_db_connection).expect("auth db: migration failed");
     drop(auth_db_connection);
 
     // man database connection
@@ -421,8 +1498,24 @@ async fn main() -> io::Result<()> {
     let main_db_pool = db_auth::Pool::new(main_db_manager).unwrap();
     let main_db_connection = main_db_pool.get().expect("main db: connection failed");
     main_db_connection.execute_batch("PRAGMA journal_mode=WAL;").expect("main db: WAL failed");
+    db_schema::migrate_main(&main_db_connection).expect("main db: migration failed");
     drop(main_db_connection);
 
+    // hourly purge of events that have been in the trash past the retention window
+    let purge_pool = main_db_pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            let cutoff = now_millis() - db_main::retention_days() * 86_400_000;
+            match purge_pool.get().map_err(|err| err.to_string()).and_then(|mut conn| db_main::purge_trash_sql(&mut conn, cutoff).map_err(|err| err.to_string())) {
+                Ok(0) => {}
+                Ok(purged) => log::info!("[OK] purged {} deleted events", purged),
+                Err(err) => log::warn!("trash purge failed: {}", err),
+            }
+        }
+    });
+
     let secret_key = get_secret_key();
 
     // ratelimiting with governor
@@ -455,6 +1548,8 @@ async fn main() -> io::Result<()> {
             }))
             // add sessions to app data
             .app_data(sessions.clone())
+            // read-only guard and audit trail for admins viewing as a student. must sit inside the identity service
+            .wrap_fn(impersonate::guard)
             // use governor ratelimiting as middleware
             .wrap(Governor::new(&governor_conf))
             // ident service
@@ -533,6 +1628,37 @@ async fn main() -> io::Result<()> {
                 web::resource("/api/v1/events/future")
                     .route(web::get().to(events_get_future)),
             )
+            .service(
+                web::resource("/api/v1/events/calendar.ics")
+                    .route(web::get().to(events_get_calendar)),
+            )
+            .service(
+                web::resource("/api/v1/calendar/{token}.ics")
+                    .route(web::get().to(calendar_get_private)),
+            )
+            .service(
+                web::resource("/api/v1/user/calendar")
+                    .route(web::get().to(user_get_calendar))
+                    .route(web::post().to(user_post_calendar)),
+            )
+            .service(
+                web::resource("/api/v1/user/calendar/reset")
+                    .route(web::post().to(user_post_calendar_reset)),
+            )
+            .service(
+                web::resource("/api/v1/events/{event_id}/rsvp")
+                    .route(web::post().to(events_post_rsvp))
+                    .route(web::delete().to(events_delete_rsvp)),
+            )
+            .service(
+                web::resource("/api/v1/events/{event_id}/checkin")
+                    .route(web::post().to(events_post_checkin)),
+            )
+            .service(
+                web::resource("/api/v1/events/{event_id}/waitlist")
+                    .route(web::post().to(events_post_waitlist))
+                    .route(web::delete().to(events_delete_waitlist)),
+            )
             .service(
                 web::resource("/api/v1/tickets_all")
                     .route(web::get().to(tickets_get_all)),
@@ -553,10 +1679,194 @@ async fn main() -> io::Result<()> {
                 web::resource("/api/v1/manage/events/delete/{event_id}")
                     .route(web::delete().to(manage_delete_event)),
             )
+            .service(
+                web::resource("/api/v1/manage/events/trash")
+                    .route(web::get().to(manage_get_trash)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/restore")
+                    .route(web::post().to(manage_restore_event)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/checkin_code")
+                    .route(web::get().to(manage_get_checkin_code)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/checkin_window")
+                    .route(web::post().to(manage_set_checkin_window)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/self_checkin")
+                    .route(web::post().to(manage_set_self_checkin)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/attendance")
+                    .route(web::get().to(manage_get_attendance)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/waitlist")
+                    .route(web::get().to(manage_get_waitlist)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/waitlist/reorder")
+                    .route(web::post().to(manage_reorder_waitlist)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/occurrences/{occurrence}/cancel")
+                    .route(web::post().to(manage_cancel_occurrence)),
+            )
+            .service(
+                web::resource("/api/v1/events/{event_id}/survey")
+                    .route(web::get().to(events_get_survey))
+                    .route(web::post().to(events_post_survey)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/survey")
+                    .route(web::post().to(manage_set_survey)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/survey/results")
+                    .route(web::get().to(manage_get_survey_results)),
+            )
+            .service(
+                web::resource("/api/v1/user/notifications")
+                    .route(web::get().to(user_get_notifications)),
+            )
+            .service(
+                web::resource("/api/v1/user/notifications/read")
+                    .route(web::post().to(user_post_notifications_read)),
+            )
             .service(
                 web::resource("/api/v1/manage/events/create")
                     .route(web::post().to(manage_create_event)),
             )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/duplicate")
+                    .route(web::post().to(manage_duplicate_event)),
+            )
+            .service(
+                web::resource("/api/v1/manage/templates/create")
+                    .route(web::post().to(manage_create_template)),
+            )
+            .service(
+                web::resource("/api/v1/manage/templates/all")
+                    .route(web::get().to(manage_get_templates)),
+            )
+            .service(
+                web::resource("/api/v1/manage/templates/{template_id}")
+                    .route(web::delete().to(manage_delete_template)),
+            )
+            .service(
+                web::resource("/api/v1/manage/templates/{template_id}/create")
+                    .route(web::post().to(manage_create_from_template)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/edit")
+                    .route(web::post().to(manage_edit_event)),
+            )
+            .service(
+                web::resource("/api/v1/manage/orgs/create")
+                    .route(web::post().to(manage_create_org)),
+            )
+            .service(
+                web::resource("/api/v1/manage/orgs/all")
+                    .route(web::get().to(manage_get_orgs)),
+            )
+            .service(
+                web::resource("/api/v1/manage/orgs/{org_id}/members")
+                    .route(web::get().to(manage_get_org_members))
+                    .route(web::post().to(manage_set_org_member)),
+            )
+            .service(
+                web::resource("/api/v1/manage/orgs/{org_id}/members/{user_id}")
+                    .route(web::delete().to(manage_remove_org_member)),
+            )
+            .service(
+                web::resource("/api/v1/manage/orgs/{org_id}/events")
+                    .route(web::get().to(manage_get_org_events)),
+            )
+            .service(
+                web::resource("/api/v1/user/orgs")
+                    .route(web::get().to(user_get_orgs)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/status")
+                    .route(web::post().to(manage_update_event_status)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/{event_id}/cancel")
+                    .route(web::post().to(manage_cancel_event)),
+            )
+            .service(
+                web::resource("/api/v1/manage/images/upload")
+                    .route(web::post().to(manage_upload_image)),
+            )
+            .service(
+                web::resource("/api/v1/images/{name}")
+                    .route(web::get().to(images_get)),
+            )
+            .service(
+                web::resource("/api/v1/manage/events/import")
+                    // school calendar exports run past the default payload limit
+                    .app_data(web::PayloadConfig::new(4 * 1024 * 1024))
+                    .route(web::post().to(manage_import_events)),
+            )
+            .service(
+                web::resource("/api/v1/manage/invites/create")
+                    .route(web::post().to(manage_create_invite)),
+            )
+            .service(
+                web::resource("/api/v1/manage/invites/all")
+                    .route(web::get().to(manage_get_invites)),
+            )
+            .service(
+                web::resource("/api/v1/manage/invites/delete/{code}")
+                    .route(web::delete().to(manage_delete_invite)),
+            )
+            .service(
+                web::resource("/api/v1/manage/auth/hashes")
+                    .route(web::get().to(manage_get_hash_report)),
+            )
+            .service(
+                web::resource("/api/v1/manage/users/{user_id}/suspend")
+                    .route(web::post().to(manage_suspend_user)),
+            )
+            .service(
+                web::resource("/api/v1/manage/users/{user_id}/unsuspend")
+                    .route(web::post().to(manage_unsuspend_user)),
+            )
+            .service(
+                web::resource("/api/v1/manage/users/{user_id}/rename")
+                    .route(web::post().to(manage_rename_user)),
+            )
+            .service(
+                web::resource("/api/v1/manage/users/{user_id}/hide")
+                    .route(web::post().to(manage_hide_user)),
+            )
+            .service(
+                web::resource("/api/v1/user/profile")
+                    .route(web::post().to(user_post_profile)),
+            )
+            .service(
+                web::resource("/api/v1/manage/names/reviews")
+                    .route(web::get().to(manage_get_name_reviews)),
+            )
+            .service(
+                web::resource("/api/v1/manage/names/reviews/{review_id}/{decision:approve|reject}")
+                    .route(web::post().to(manage_resolve_name_review)),
+            )
+            .service(
+                web::resource("/api/v1/manage/impersonate/end")
+                    .route(web::post().to(manage_end_impersonation)),
+            )
+            .service(
+                web::resource("/api/v1/manage/impersonate/{user_id}")
+                    .route(web::post().to(manage_start_impersonation)),
+            )
+            .service(
+                web::resource("/api/v1/manage/audit")
+                    .route(web::get().to(manage_get_audit_log)),
+            )
             .route(
                 "/api/chatgpt", web::post().to(chatgpt_handler)
             )
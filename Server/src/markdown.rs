@@ -0,0 +1,134 @@
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+use std::collections::HashSet;
+
+// links can only go to web pages and email, so sign-up forms and permission slips work but scripts don't
+const URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+fn options() -> Options {
+    Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES
+}
+
+// event details as HTML for clients that render it. raw HTML in the source is cleaned out rather than trusted
+pub fn to_html(markdown: &str) -> String {
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, Parser::new_ext(markdown, options()));
+    ammonia::Builder::default()
+        .url_schemes(HashSet::from(URL_SCHEMES))
+        .clean(&rendered)
+        .to_string()
+}
+
+// event details for places without formatting, like the back of a pass. links are written out after their text
+pub fn to_plain(markdown: &str) -> String {
+    let mut plain = String::new();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut links: Vec<(String, usize)> = Vec::new();
+    // the contents of raw script and style tags aren't text anyone wrote for people
+    let mut hidden = false;
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Html(tag) | Event::InlineHtml(tag) => {
+                let tag = tag.to_lowercase();
+                if tag.contains("<script") || tag.contains("<style") {
+                    hidden = true;
+                }
+                if tag.contains("</script") || tag.contains("</style") {
+                    hidden = false;
+                }
+            }
+            Event::Text(_) | Event::Code(_) if hidden => {}
+            Event::Text(text) | Event::Code(text) => plain.push_str(&text),
+            Event::SoftBreak => plain.push(' '),
+            Event::HardBreak | Event::Rule => plain.push('\n'),
+            Event::Start(Tag::List(start)) => {
+                if !lists.is_empty() {
+                    plain.push('\n');
+                }
+                lists.push(start);
+            }
+            Event::Start(Tag::Item) => {
+                plain.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        plain.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => plain.push_str("• "),
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => links.push((dest_url.to_string(), plain.len())),
+            Event::End(TagEnd::Link) => {
+                if let Some((url, start)) = links.pop() {
+                    // autolinks already show their address
+                    if safe_url(&url) && plain[start..] != url && plain[start..] != *url.trim_start_matches("mailto:") {
+                        plain.push_str(&format!(" ({})", url));
+                    }
+                }
+            }
+            Event::End(TagEnd::Item) if !plain.ends_with('\n') => plain.push('\n'),
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    plain.push('\n');
+                }
+            }
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::BlockQuote(_) | TagEnd::Table) => plain.push_str("\n\n"),
+            Event::End(TagEnd::TableCell) => plain.push('\t'),
+            Event::End(TagEnd::TableRow | TagEnd::TableHead) => plain.push('\n'),
+            _ => {}
+        }
+    }
+    // paragraphs inside list items would otherwise leave gaps
+    let mut collapsed = String::with_capacity(plain.len());
+    for line in plain.trim().split('\n') {
+        if line.trim().is_empty() && collapsed.ends_with("\n\n") {
+            continue;
+        }
+        collapsed.push_str(line.trim_end());
+        collapsed.push('\n');
+    }
+    collapsed.trim_end().to_string()
+}
+
+fn safe_url(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| URL_SCHEMES.contains(&scheme.to_lowercase().as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_written_after_their_text() {
+        assert_eq!(to_plain("Sign up [here](https://example.com/form)."), "Sign up here (https://example.com/form).");
+        assert_eq!(to_plain("Email [the office](mailto:office@example.com)"), "Email the office (mailto:office@example.com)");
+    }
+
+    #[test]
+    fn autolinks_and_unsafe_links_get_no_suffix() {
+        assert_eq!(to_plain("<https://example.com>"), "https://example.com");
+        assert_eq!(to_plain("<office@example.com>"), "office@example.com");
+        assert_eq!(to_plain("[click](javascript:alert(1))"), "click");
+    }
+
+    #[test]
+    fn nested_lists_are_indented_and_numbered() {
+        let markdown = "1. Bring\n   - a pencil\n   - a calculator\n2. Arrive early\n\nThanks";
+        assert_eq!(to_plain(markdown), "1. Bring\n  • a pencil\n  • a calculator\n2. Arrive early\n\nThanks");
+    }
+
+    #[test]
+    fn scripts_and_styles_are_dropped() {
+        assert_eq!(to_plain("Hello\n\n<script>alert('hi')</script>\n\nthere"), "Hello\n\nthere");
+        assert_eq!(to_plain("<style>p { color: red }</style>\n\nVisible"), "Visible");
+        assert_eq!(to_plain("inline <script>steal()</script> text"), "inline  text");
+    }
+
+    #[test]
+    fn html_keeps_safe_links_only() {
+        let html = to_html("[form](https://example.com) [bad](javascript:alert(1)) <script>alert(1)</script>");
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(!html.contains("javascript"));
+        assert!(!html.contains("<script"));
+    }
+}
@@ -0,0 +1,170 @@
+use actix_web::{error, Error, HttpResponse};
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 500;
+
+// ?limit=&cursor=&sort= on list endpoints. sort is a column name, prefixed with - for descending
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+}
+
+// a column a list can be sorted by
+pub struct SortColumn {
+    pub name: &'static str,
+    pub column: &'static str, // in sql, for lists paged in the database
+}
+
+// sort values, and the unique tiebreak after them, that cursors are made of
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key {
+    Int(i64),
+    Text(String),
+}
+
+impl Key {
+    fn to_value(&self) -> Value {
+        match self {
+            Key::Int(value) => Value::Integer(*value),
+            Key::Text(value) => Value::Text(value.clone()),
+        }
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Key::Int(value) => format!("i{}", value),
+            Key::Text(value) => format!("t{}", value),
+        }
+    }
+
+    fn decode(value: &str) -> Option<Key> {
+        match value.split_at_checked(1)? {
+            ("i", value) => value.parse::<i64>().ok().map(Key::Int),
+            ("t", value) => Some(Key::Text(value.to_string())),
+            _ => None,
+        }
+    }
+}
+
+// one page of a list, read from a PageQuery
+pub struct Page {
+    pub limit: usize,
+    pub sort: &'static str, // the chosen column's name
+    column: &'static str,
+    descending: bool,
+    after: Option<(Key, Key)>,
+}
+
+pub struct Paged<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
+}
+
+impl PageQuery {
+    pub fn page(&self, columns: &[SortColumn], default_sort: &str) -> Result<Page, Error> {
+        let sort = self.sort.as_deref().unwrap_or(default_sort);
+        let (name, descending) = match sort.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (sort, false),
+        };
+        let column = columns.iter().find(|column| column.name == name).ok_or_else(|| {
+            let names: Vec<&str> = columns.iter().map(|column| column.name).collect();
+            error::ErrorBadRequest(serde_json::json!({ "status": "bad_sort", "sorts": names }).to_string())
+        })?;
+        let after = match &self.cursor {
+            Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| error::ErrorBadRequest("{\"status\": \"bad_cursor\"}"))?),
+            None => None,
+        };
+        Ok(Page {
+            limit: self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+            sort: column.name,
+            column: column.column,
+            descending,
+            after,
+        })
+    }
+}
+
+// cursors are hex so clients can pass them back in a query string as is
+fn encode_cursor(sort: &Key, tiebreak: &Key) -> String {
+    format!("{}\u{1f}{}", sort.encode(), tiebreak.encode()).bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<(Key, Key)> {
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| cursor.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    let decoded = String::from_utf8(bytes).ok()?;
+    let (sort, tiebreak) = decoded.split_once('\u{1f}')?;
+    Some((Key::decode(sort)?, Key::decode(tiebreak)?))
+}
+
+impl Page {
+    // the cursor condition (TRUE on the first page) and the ordering and limit, for a query paged in sql.
+    // one row more than the limit is fetched to tell whether there's a next page
+    pub fn sql(&self, id_column: &str) -> (String, String, Vec<Value>) {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        let order = format!("ORDER BY {0} {2}, {1} {2} LIMIT {3}", self.column, id_column, direction, self.limit + 1);
+        match &self.after {
+            Some((sort, tiebreak)) => (
+                format!("({}, {}) {} (?, ?)", self.column, id_column, if self.descending { "<" } else { ">" }),
+                order,
+                vec![sort.to_value(), tiebreak.to_value()],
+            ),
+            None => ("TRUE".to_string(), order, Vec::new()),
+        }
+    }
+
+    // trims rows fetched with sql() to the page and works out the next cursor
+    pub fn finish<T>(&self, mut rows: Vec<T>, total: usize, key: impl Fn(&T, &str) -> (Key, Key)) -> Paged<T> {
+        let next_cursor = if rows.len() > self.limit {
+            rows.truncate(self.limit);
+            rows.last().map(|last| {
+                let (sort, tiebreak) = key(last, self.sort);
+                encode_cursor(&sort, &tiebreak)
+            })
+        } else {
+            None
+        };
+        Paged { items: rows, total, next_cursor }
+    }
+
+    // pages a list that's already in memory, for lists built by filtering or expanding rows
+    pub fn apply<T>(&self, mut items: Vec<T>, key: impl Fn(&T, &str) -> (Key, Key)) -> Paged<T> {
+        let total = items.len();
+        let order = |a: &(Key, Key), b: &(Key, Key)| -> Ordering {
+            if self.descending {
+                b.cmp(a)
+            } else {
+                a.cmp(b)
+            }
+        };
+        items.sort_by(|a, b| order(&key(a, self.sort), &key(b, self.sort)));
+        if let Some(after) = &self.after {
+            items.retain(|item| order(&key(item, self.sort), after) == Ordering::Greater);
+        }
+        items.truncate(self.limit + 1);
+        self.finish(items, total, key)
+    }
+}
+
+// pages are sent as plain arrays so older clients keep working, with the count and cursor in headers
+pub fn respond<T: Serialize>(paged: Paged<T>, cache_control: &str) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response.insert_header(("Cache-Control", cache_control.to_string()));
+    response.insert_header(("X-Total-Count", paged.total.to_string()));
+    if let Some(cursor) = paged.next_cursor {
+        response.insert_header(("X-Next-Cursor", cursor));
+    }
+    response.json(paged.items)
+}
@@ -1,27 +1,21 @@
-use chrono::prelude::{DateTime, Utc};
 use serde_json::{json, Value};
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
 
-use crate::{db_main, db_auth};
-
-fn millis_to_system_time(millis: i64) -> SystemTime {
-    UNIX_EPOCH + Duration::new(millis as u64 / 1000, ((millis % 1000) * 1_000_000) as u32)
-}
-
-fn iso8601(st: &std::time::SystemTime) -> String {
-    let dt: DateTime<Utc> = st.clone().into();
-    format!("{}", dt.format("%+"))
-}
+use crate::{db_main, db_auth, timezone};
 
 pub fn generate_pass_json(ticket: db_main::Ticket, event: db_main::Event, user: db_auth::User) -> Value {
+    let zone = timezone::zone(&event.timezone);
     json!(
         {
             "formatVersion": 1,
             "passTypeIdentifier": "pass.com.jayagra.ma-central",
             "serialNumber": format!("{}", ticket.id),
             "teamIdentifier": "D6MFYYVHA8",
-            "relevantDate": iso8601(&millis_to_system_time(event.start_time)),
-            "expirationDate": iso8601(&millis_to_system_time(event.end_time + 86400000)),
+            // wallet greys out passes for cancelled events. passes have no webServiceURL, so wallet never refreshes them on its own:
+            // this only reaches passes downloaded again, which the cancellation notice asks holders to do
+            "voided": ticket.status == "void",
+            // in the event's zone, so wallet shows the time on the school's clock wherever the phone is
+            "relevantDate": timezone::local_string(&zone, event.start_time),
+            "expirationDate": timezone::local_string(&zone, event.end_time + 86400000),
             "locations": [
                 {
                     "longitude": event.longitude,
@@ -52,7 +46,8 @@ pub fn generate_pass_json(ticket: db_main::Ticket, event: db_main::Event, user:
                         "key": "date",
                         "label": "DATE",
                         "timeStyle": "PKDateStyleShort",
-                        "value": iso8601(&millis_to_system_time(event.start_time))
+                        "ignoresTimeZone": true,
+                        "value": timezone::local_string(&zone, event.start_time)
                     },
                     {
                         "key": "loc",
@@ -71,7 +66,7 @@ pub fn generate_pass_json(ticket: db_main::Ticket, event: db_main::Event, user:
                     {
                         "key": "description",
                         "label": "Event Description",
-                        "value": format!("{}", event.details)
+                        "value": event.details_text
                     },
                     {
                         "key": "terms",
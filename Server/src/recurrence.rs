@@ -0,0 +1,206 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use chrono_tz::Tz;
+
+use crate::timezone;
+
+// hard stop so a rule without COUNT or UNTIL can't expand forever
+const MAX_OCCURRENCES: usize = 1000;
+// longest gaps a rule can have, a year either way
+const MAX_WEEKLY_INTERVAL: u32 = 52;
+const MAX_MONTHLY_INTERVAL: u32 = 12;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Weekly,
+    Monthly,
+}
+
+// the RFC 5545 RRULE subset events support: FREQ=WEEKLY|MONTHLY, INTERVAL, COUNT or UNTIL, and BYDAY for weekly rules
+pub struct Rule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<i64>,
+    pub by_day: Vec<Weekday>,
+}
+
+pub fn parse(rrule: &str) -> Result<Rule, String> {
+    let rrule = rrule.trim();
+    let rrule = rrule.strip_prefix("RRULE:").unwrap_or(rrule);
+    let mut frequency = None;
+    let mut interval = 1;
+    let mut count = None;
+    let mut until = None;
+    let mut by_day = Vec::new();
+    for part in rrule.split(';').filter(|part| !part.is_empty()) {
+        let (key, value) = part.split_once('=').ok_or_else(|| format!("bad rule part {}", part))?;
+        match key.to_ascii_uppercase().as_str() {
+            "FREQ" => {
+                frequency = Some(match value.to_ascii_uppercase().as_str() {
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    other => return Err(format!("unsupported FREQ {}", other)),
+                })
+            }
+            "INTERVAL" => interval = value.parse::<u32>().ok().filter(|interval| *interval > 0).ok_or("bad INTERVAL")?,
+            "COUNT" => count = Some(value.parse::<u32>().ok().filter(|count| *count > 0).ok_or("bad COUNT")?),
+            "UNTIL" => until = Some(parse_until(value)?),
+            "BYDAY" => by_day = value.split(',').map(parse_weekday).collect::<Result<Vec<Weekday>, String>>()?,
+            // weeks always start on monday
+            "WKST" => {}
+            other => return Err(format!("unsupported rule part {}", other)),
+        }
+    }
+    let frequency = frequency.ok_or("FREQ is required")?;
+    if count.is_some() && until.is_some() {
+        return Err("COUNT and UNTIL can't be combined".to_string());
+    }
+    let max_interval = match frequency {
+        Frequency::Weekly => MAX_WEEKLY_INTERVAL,
+        Frequency::Monthly => MAX_MONTHLY_INTERVAL,
+    };
+    if interval > max_interval {
+        return Err(format!("INTERVAL can be at most {}", max_interval));
+    }
+    if frequency == Frequency::Monthly && !by_day.is_empty() {
+        return Err("BYDAY is only supported on weekly rules".to_string());
+    }
+    by_day.sort_by_key(Weekday::num_days_from_monday);
+    by_day.dedup();
+    Ok(Rule { frequency, interval, count, until, by_day })
+}
+
+fn parse_until(value: &str) -> Result<i64, String> {
+    if let Ok(until) = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S") {
+        return Ok(until.and_utc().timestamp_millis());
+    }
+    // a bare date includes the whole day
+    NaiveDate::parse_from_str(value, "%Y%m%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .map(|until| until.and_utc().timestamp_millis())
+        .ok_or_else(|| format!("bad UNTIL {}", value))
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday, String> {
+    match value.trim().to_ascii_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        other => Err(format!("unsupported BYDAY {}", other)),
+    }
+}
+
+// start times (epoch millis) of every occurrence from the first one through horizon, minus exceptions. COUNT is applied before exceptions, as in RFC 5545.
+// rules repeat on the wall clock of the event's zone, so a 6pm meeting stays at 6pm across DST changes
+pub fn occurrences(rule: &Rule, start_time: i64, exdates: &[i64], horizon: i64, zone: &Tz) -> Vec<i64> {
+    let start = match timezone::to_local(zone, start_time) {
+        Some(start) => start.naive_local(),
+        None => return Vec::new(),
+    };
+    let mut candidates = Vec::new();
+    let mut push = |candidate: NaiveDateTime| -> bool {
+        let millis = match timezone::from_local(zone, candidate) {
+            Some(millis) => millis,
+            None => return true,
+        };
+        if millis < start_time {
+            return true;
+        }
+        if millis > horizon || rule.until.is_some_and(|until| millis > until) || candidates.len() >= MAX_OCCURRENCES {
+            return false;
+        }
+        candidates.push(millis);
+        rule.count.is_none_or(|count| candidates.len() < count as usize)
+    };
+    match rule.frequency {
+        Frequency::Weekly => {
+            let days = if rule.by_day.is_empty() { vec![start.weekday()] } else { rule.by_day.clone() };
+            let week_start = match start.date().checked_sub_signed(Duration::days(start.weekday().num_days_from_monday() as i64)) {
+                Some(week_start) => week_start,
+                None => return Vec::new(),
+            };
+            // dates past what chrono can represent end the series
+            'weeks: for week in 0.. {
+                let base = match week_start.checked_add_signed(Duration::weeks(week * rule.interval as i64)) {
+                    Some(base) => base,
+                    None => break,
+                };
+                for day in &days {
+                    let date = match base.checked_add_signed(Duration::days(day.num_days_from_monday() as i64)) {
+                        Some(date) => date,
+                        None => break 'weeks,
+                    };
+                    if !push(date.and_time(start.time())) {
+                        break 'weeks;
+                    }
+                }
+            }
+        }
+        Frequency::Monthly => {
+            let first_month = start.year() as i64 * 12 + start.month0() as i64;
+            for step in 0..(MAX_OCCURRENCES as i64 * 4) {
+                let month = first_month + step * rule.interval as i64;
+                // months without this day (the 31st, say) are skipped, not clamped
+                if let Some(date) = NaiveDate::from_ymd_opt((month / 12) as i32, (month % 12) as u32 + 1, start.day()) {
+                    if !push(date.and_time(start.time())) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    candidates.retain(|candidate| !exdates.contains(candidate));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn la_times(times: &[i64]) -> Vec<String> {
+        times.iter().map(|time| timezone::local_string(&chrono_tz::America::Los_Angeles, *time)).collect()
+    }
+
+    #[test]
+    fn weekly_stays_on_the_wall_clock_across_dst() {
+        let zone = chrono_tz::America::Los_Angeles;
+        let start = timezone::from_local(&zone, NaiveDate::from_ymd_opt(2024, 10, 21).unwrap().and_hms_opt(18, 0, 0).unwrap()).unwrap();
+        let rule = parse("FREQ=WEEKLY;COUNT=4").unwrap();
+        assert_eq!(
+            la_times(&occurrences(&rule, start, &[], i64::MAX, &zone)),
+            ["2024-10-21T18:00:00-07:00", "2024-10-28T18:00:00-07:00", "2024-11-04T18:00:00-08:00", "2024-11-11T18:00:00-08:00"],
+        );
+    }
+
+    #[test]
+    fn monthly_moves_skipped_times_past_the_gap() {
+        let zone = chrono_tz::America::Los_Angeles;
+        let start = timezone::from_local(&zone, NaiveDate::from_ymd_opt(2024, 2, 10).unwrap().and_hms_opt(2, 30, 0).unwrap()).unwrap();
+        let rule = parse("FREQ=MONTHLY;COUNT=3").unwrap();
+        assert_eq!(
+            la_times(&occurrences(&rule, start, &[], i64::MAX, &zone)),
+            ["2024-02-10T02:30:00-08:00", "2024-03-10T03:30:00-07:00", "2024-04-10T02:30:00-07:00"],
+        );
+    }
+
+    #[test]
+    fn exdates_remove_local_occurrences() {
+        let zone = chrono_tz::America::Los_Angeles;
+        let start = timezone::from_local(&zone, NaiveDate::from_ymd_opt(2024, 10, 21).unwrap().and_hms_opt(18, 0, 0).unwrap()).unwrap();
+        let rule = parse("FREQ=WEEKLY;COUNT=3").unwrap();
+        let all = occurrences(&rule, start, &[], i64::MAX, &zone);
+        assert_eq!(occurrences(&rule, start, &[all[1]], i64::MAX, &zone), [all[0], all[2]]);
+    }
+
+    #[test]
+    fn intervals_are_capped() {
+        assert!(parse("FREQ=WEEKLY;INTERVAL=52").is_ok());
+        assert!(parse("FREQ=WEEKLY;INTERVAL=4000000000").is_err());
+        assert!(parse("FREQ=MONTHLY;INTERVAL=13").is_err());
+    }
+}
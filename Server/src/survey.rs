@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const KINDS: [&str; 3] = ["rating", "text", "choice"];
+pub const MAX_RATING: i64 = 5;
+const MAX_TEXT_CHARS: usize = 2000;
+const MAX_OPEN_DAYS: i64 = 90;
+
+// attendees can answer for a week after the event unless the survey says otherwise
+fn default_open_days() -> i64 {
+    7
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QuestionData {
+    pub kind: String, // one of KINDS
+    pub prompt: String,
+    #[serde(default)]
+    pub options: Vec<String>, // the choices of a choice question
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SurveySettings {
+    #[serde(default)]
+    pub questions: Vec<QuestionData>,
+    #[serde(default)]
+    pub bonus_points: i64,
+    #[serde(default = "default_open_days")]
+    pub open_days: i64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Question {
+    pub id: i64,
+    pub kind: String,
+    pub prompt: String,
+    pub options: Vec<String>,
+    pub required: bool,
+}
+
+#[derive(Serialize)]
+pub struct Survey {
+    pub event_id: i64,
+    pub bonus_points: i64,
+    pub open_days: i64,
+    pub questions: Vec<Question>,
+}
+
+// what most events want to know, used when a survey is set up without questions
+pub fn default_questions() -> Vec<QuestionData> {
+    vec![
+        QuestionData { kind: "rating".to_string(), prompt: "How was it?".to_string(), options: Vec::new(), required: true },
+        QuestionData { kind: "text".to_string(), prompt: "Anything else you'd like to tell the organizers?".to_string(), options: Vec::new(), required: false },
+    ]
+}
+
+pub fn validate_settings(settings: &SurveySettings) -> Result<(), &'static str> {
+    if settings.bonus_points < 0 {
+        return Err("bad_bonus_points");
+    }
+    if !(1..=MAX_OPEN_DAYS).contains(&settings.open_days) {
+        return Err("bad_open_days");
+    }
+    for question in &settings.questions {
+        if !KINDS.contains(&question.kind.as_str()) {
+            return Err("bad_kind");
+        }
+        if question.prompt.trim().is_empty() {
+            return Err("bad_prompt");
+        }
+        if question.kind == "choice" && question.options.iter().filter(|option| !option.trim().is_empty()).count() < 2 {
+            return Err("bad_options");
+        }
+    }
+    Ok(())
+}
+
+// ratings come in as numbers, everything else as text
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum AnswerValue {
+    Number(i64),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+pub struct AnswerData {
+    pub question_id: i64,
+    pub value: AnswerValue,
+}
+
+#[derive(Deserialize)]
+pub struct ResponseData {
+    pub answers: Vec<AnswerData>,
+}
+
+// checks answers against the questions and returns them ready to store. blank optional answers are dropped
+pub fn validate_answers(questions: &[Question], answers: Vec<AnswerData>) -> Result<Vec<(i64, String)>, String> {
+    let mut stored: Vec<(i64, String)> = Vec::new();
+    for answer in answers {
+        let question = questions.iter().find(|question| question.id == answer.question_id).ok_or("unknown_question")?;
+        if stored.iter().any(|(question_id, _)| *question_id == question.id) {
+            return Err("duplicate_answer".to_string());
+        }
+        let value = match (question.kind.as_str(), answer.value) {
+            ("rating", AnswerValue::Number(rating)) if (1..=MAX_RATING).contains(&rating) => rating.to_string(),
+            ("text", AnswerValue::Text(text)) if text.chars().count() <= MAX_TEXT_CHARS => text.trim().to_string(),
+            ("choice", AnswerValue::Text(choice)) if question.options.contains(&choice) => choice,
+            _ => return Err(format!("bad_answer_{}", question.id)),
+        };
+        if !value.is_empty() {
+            stored.push((question.id, value));
+        }
+    }
+    if let Some(question) = questions.iter().find(|question| question.required && !stored.iter().any(|(question_id, _)| *question_id == question.id)) {
+        return Err(format!("missing_answer_{}", question.id));
+    }
+    Ok(stored)
+}
+
+#[derive(Serialize)]
+pub struct QuestionResults {
+    pub id: i64,
+    pub kind: String,
+    pub prompt: String,
+    pub answers: usize,
+    pub average: Option<f64>, // ratings only
+    pub distribution: Vec<usize>, // ratings only, count of each rating from 1 up
+    pub choices: HashMap<String, usize>, // choice questions only
+    pub comments: Vec<String>, // text questions only, without names so students answer honestly
+}
+
+#[derive(Serialize)]
+pub struct SurveyResults {
+    pub event_id: i64,
+    pub attendees: usize,
+    pub responses: usize,
+    pub response_rate: f64,
+    pub questions: Vec<QuestionResults>,
+}
+
+pub fn results(survey: &Survey, attendees: usize, responses: usize, answers: &[(i64, String)]) -> SurveyResults {
+    let questions = survey
+        .questions
+        .iter()
+        .map(|question| {
+            let values: Vec<&String> = answers.iter().filter(|(question_id, _)| *question_id == question.id).map(|(_, value)| value).collect();
+            let mut results = QuestionResults {
+                id: question.id,
+                kind: question.kind.clone(),
+                prompt: question.prompt.clone(),
+                answers: values.len(),
+                average: None,
+                distribution: Vec::new(),
+                choices: HashMap::new(),
+                comments: Vec::new(),
+            };
+            match question.kind.as_str() {
+                "rating" => {
+                    let ratings: Vec<i64> = values.iter().filter_map(|value| value.parse::<i64>().ok()).collect();
+                    results.distribution = (1..=MAX_RATING).map(|rating| ratings.iter().filter(|value| **value == rating).count()).collect();
+                    if !ratings.is_empty() {
+                        results.average = Some(ratings.iter().sum::<i64>() as f64 / ratings.len() as f64);
+                    }
+                }
+                "choice" => {
+                    results.choices = question.options.iter().map(|option| (option.clone(), 0)).collect();
+                    for value in values {
+                        *results.choices.entry(value.clone()).or_insert(0) += 1;
+                    }
+                }
+                _ => results.comments = values.into_iter().cloned().collect(),
+            }
+            results
+        })
+        .collect();
+    SurveyResults {
+        event_id: survey.event_id,
+        attendees,
+        responses,
+        response_rate: if attendees == 0 { 0.0 } else { responses as f64 / attendees as f64 },
+        questions,
+    }
+}
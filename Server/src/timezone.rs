@@ -0,0 +1,149 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use std::env;
+
+// the school's zone, for events that don't name their own
+pub fn default_timezone() -> Tz {
+    env::var("EVENT_TIMEZONE").ok().and_then(|zone| zone.parse::<Tz>().ok()).unwrap_or(chrono_tz::America::Los_Angeles)
+}
+
+// an event's zone. events stored without one follow the deployment's
+pub fn zone(name: &str) -> Tz {
+    name.parse::<Tz>().unwrap_or_else(|_| default_timezone())
+}
+
+// event times the server accepts, 2000 through 2099. anything outside is a typo or a value in the wrong unit
+const EARLIEST_TIME: i64 = 946_684_800_000;
+const LATEST_TIME: i64 = 4_102_444_800_000;
+
+pub fn time_in_range(millis: i64) -> bool {
+    (EARLIEST_TIME..LATEST_TIME).contains(&millis)
+}
+
+// empty means the deployment's zone
+pub fn valid(name: &str) -> bool {
+    name.is_empty() || name.parse::<Tz>().is_ok()
+}
+
+// wall clock time in a zone to epoch millis. times skipped by a DST change are read as the first valid time after them, and times it repeats as the first of the two
+pub fn from_local(zone: &Tz, local: NaiveDateTime) -> Option<i64> {
+    zone.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| zone.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map(|time| time.timestamp_millis())
+}
+
+pub fn to_local(zone: &Tz, millis: i64) -> Option<DateTime<Tz>> {
+    Utc.timestamp_millis_opt(millis).single().map(|time| time.with_timezone(zone))
+}
+
+// RFC 3339 with the zone's offset at that moment, e.g. 2024-11-04T18:00:00-08:00
+pub fn local_string(zone: &Tz, millis: i64) -> String {
+    to_local(zone, millis).map(|time| time.format("%Y-%m-%dT%H:%M:%S%:z").to_string()).unwrap_or_default()
+}
+
+fn offset_seconds(zone: &Tz, millis: i64) -> i32 {
+    to_local(zone, millis).map(|time| time.offset().fix().local_minus_utc()).unwrap_or(0)
+}
+
+pub struct Transition {
+    pub at: i64, // epoch millis of the change
+    pub from: i32, // offset from UTC in seconds before it
+    pub to: i32, // and after it
+    pub daylight: bool,
+    pub abbreviation: String,
+}
+
+// every offset change between from and to, plus the offset in effect at from so there's always at least one.
+// the range is cut to a year, callers covering more ask a year at a time
+pub fn transitions(zone: &Tz, from: i64, to: i64) -> Vec<Transition> {
+    let to = to.min(from + 366 * 86_400_000);
+    let describe = |at: i64, before: i32| {
+        let local = to_local(zone, at);
+        Transition {
+            at,
+            from: before,
+            to: offset_seconds(zone, at),
+            daylight: local.as_ref().is_some_and(|time| !time.offset().dst_offset().is_zero()),
+            abbreviation: local.as_ref().and_then(|time| time.offset().abbreviation().map(str::to_string)).unwrap_or_default(),
+        }
+    };
+    let mut found = vec![describe(from, offset_seconds(zone, from))];
+    // zones change at most a few times a year and never twice in a day, so check daily and narrow down to the second
+    let day = 86_400_000;
+    let mut start = from;
+    while start < to {
+        let end = start + day;
+        let before = offset_seconds(zone, start);
+        if offset_seconds(zone, end) != before {
+            let (mut low, mut high) = (start, end);
+            while high - low > 1000 {
+                let middle = low + (high - low) / 2;
+                if offset_seconds(zone, middle) == before {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            // changes happen on whole seconds
+            found.push(describe((low.div_euclid(1000) + 1) * 1000, before));
+        }
+        start = end;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn skipped_times_move_past_the_gap() {
+        let zone = chrono_tz::America::Los_Angeles;
+        // 2:30 doesn't exist on 2024-03-10, clocks go from 2:00 PST to 3:00 PDT
+        let millis = from_local(&zone, local(2024, 3, 10, 2, 30)).unwrap();
+        assert_eq!(local_string(&zone, millis), "2024-03-10T03:30:00-07:00");
+    }
+
+    #[test]
+    fn repeated_times_take_the_first() {
+        let zone = chrono_tz::America::Los_Angeles;
+        // 1:30 happens twice on 2024-11-03, first in PDT
+        let millis = from_local(&zone, local(2024, 11, 3, 1, 30)).unwrap();
+        assert_eq!(local_string(&zone, millis), "2024-11-03T01:30:00-07:00");
+        assert_eq!(local_string(&zone, millis + 3_600_000), "2024-11-03T01:30:00-08:00");
+    }
+
+    #[test]
+    fn transitions_find_both_changes() {
+        let zone = chrono_tz::America::Los_Angeles;
+        let from = from_local(&zone, local(2024, 1, 1, 0, 0)).unwrap();
+        let to = from_local(&zone, local(2025, 1, 1, 0, 0)).unwrap();
+        let found = transitions(&zone, from, to);
+        assert_eq!(found.len(), 3);
+        assert_eq!(local_string(&zone, found[1].at), "2024-03-10T03:00:00-07:00");
+        assert_eq!((found[1].from, found[1].to, found[1].daylight), (-8 * 3600, -7 * 3600, true));
+        assert_eq!(local_string(&zone, found[2].at), "2024-11-03T01:00:00-08:00");
+        assert_eq!((found[2].from, found[2].to, found[2].daylight), (-7 * 3600, -8 * 3600, false));
+    }
+
+    #[test]
+    fn transitions_stop_after_a_year() {
+        let zone = chrono_tz::America::Los_Angeles;
+        let from = from_local(&zone, local(2024, 1, 1, 0, 0)).unwrap();
+        assert_eq!(transitions(&zone, from, i64::MAX / 2).len(), 3);
+    }
+
+    #[test]
+    fn times_outside_the_range_are_rejected() {
+        assert!(time_in_range(1_730_000_000_000));
+        // microseconds instead of milliseconds
+        assert!(!time_in_range(1_730_000_000_000_000));
+        assert!(!time_in_range(0));
+    }
+}